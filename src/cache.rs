@@ -0,0 +1,522 @@
+use crate::comms::*;
+use crate::defines::*;
+use nanovg::Image;
+use nanovg_sys::nvgCreateFontMem;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use crate::types::NanoContext;
+
+struct Entry<T> {
+    value: T,
+    refs: u32,
+}
+
+struct Slot<T> {
+    key: String,
+    entry: Option<Entry<T>>,
+    // given to Elixir by CMD_NEW_TX_ID or CMD_NEW_FONT_ID
+    handed_out: bool,
+}
+
+pub enum Lookup<'a, T: 'a> {
+    Found(&'a T),
+    Missing(&'a str),
+    Unknown,
+}
+
+// how many missing keys and unknown handles are remembered before the
+// reports start over
+const MAX_REPORTED: usize = 1024;
+
+// resources of one kind keyed by Scenic's hash strings. every CMD_CACHE_LOAD
+// of a key adds a reference and every CMD_CACHE_RELEASE drops one, the
+// resource is freed with the last one
+pub struct RefTable<T> {
+    // a handle indexes `slots`. a handed out one stays bound to its key for
+    // the life of the driver, whether the resource is resident or not. any
+    // other slot is freed with its resource and reused for the next key
+    handles: HashMap<String, u32>,
+    slots: Vec<Option<Slot<T>>>,
+    free: Vec<u32>,
+    // keys already reported missing, so a script drawn every frame does not
+    // send the same miss every frame
+    missed: RefCell<HashSet<String>>,
+    unknown: RefCell<HashSet<u32>>,
+}
+
+impl<T> RefTable<T> {
+    pub fn new() -> RefTable<T> {
+        RefTable {
+            handles: Default::default(),
+            slots: vec![],
+            free: vec![],
+            missed: Default::default(),
+            unknown: Default::default(),
+        }
+    }
+
+    // the handle to hand out for `key`
+    pub fn handle(&mut self, key: &str) -> u32 {
+        let handle = self.slot(key);
+        self.slot_mut(handle).handed_out = true;
+        handle
+    }
+
+    fn slot(&mut self, key: &str) -> u32 {
+        if let Some(handle) = self.handles.get(key) {
+            return *handle;
+        }
+        let slot = Slot {
+            key: key.to_string(),
+            entry: None,
+            handed_out: false,
+        };
+        let handle = match self.free.pop() {
+            Some(handle) => {
+                self.slots[handle as usize] = Some(slot);
+                handle
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.handles.insert(key.to_string(), handle);
+        handle
+    }
+
+    fn slot_mut(&mut self, handle: u32) -> &mut Slot<T> {
+        self.slots[handle as usize]
+            .as_mut()
+            .expect("handles only point at live slots")
+    }
+
+    fn entry_mut(&mut self, key: &str) -> Option<&mut Entry<T>> {
+        let handle = *self.handles.get(key)?;
+        self.slot_mut(handle).entry.as_mut()
+    }
+
+    // returns the reference count after loading, `make` only runs for keys
+    // that are not resident yet
+    pub fn load<E>(&mut self, key: &str, make: impl FnOnce() -> Result<T, E>) -> Result<u32, E> {
+        if let Some(entry) = self.entry_mut(key) {
+            entry.refs += 1;
+            return Ok(entry.refs);
+        }
+        let value = make()?;
+        self.insert(key, value);
+        Ok(1)
+    }
+
+    // the old put commands replace whatever is there without counting
+    pub fn put(&mut self, key: &str, value: T) -> Option<T> {
+        match self.entry_mut(key) {
+            Some(entry) => Some(::std::mem::replace(&mut entry.value, value)),
+            None => {
+                self.insert(key, value);
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: &str, value: T) {
+        let handle = self.slot(key);
+        self.slot_mut(handle).entry = Some(Entry {
+            value: value,
+            refs: 1,
+        });
+        self.missed.borrow_mut().remove(key);
+    }
+
+    // returns the resource once the last reference is gone
+    pub fn release(&mut self, key: &str) -> Option<T> {
+        let refs = {
+            let entry = self.entry_mut(key)?;
+            entry.refs -= 1;
+            entry.refs
+        };
+        if refs == 0 {
+            self.remove(key)
+        } else {
+            None
+        }
+    }
+
+    // drops the resource whatever its reference count
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let handle = *self.handles.get(key)?;
+        let slot = self.slot_mut(handle);
+        let value = slot.entry.take().map(|entry| entry.value);
+        if !slot.handed_out {
+            self.slots[handle as usize] = None;
+            self.handles.remove(key);
+            self.free.push(handle);
+        }
+        value
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        let handle = *self.handles.get(key)?;
+        self.slots[handle as usize]
+            .as_ref()
+            .and_then(|slot| slot.entry.as_ref())
+            .map(|entry| &entry.value)
+    }
+
+    pub fn get_by_handle(&self, handle: u32) -> Lookup<T> {
+        match self.slots.get(handle as usize) {
+            Some(Some(Slot {
+                entry: Some(entry), ..
+            })) => Lookup::Found(&entry.value),
+            Some(Some(Slot {
+                key, entry: None, ..
+            })) => Lookup::Missing(key),
+            _ => Lookup::Unknown,
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    // true the first time a missing key is asked for since it was last loaded
+    pub fn first_miss(&self, key: &str) -> bool {
+        !self.contains(key) && first_report(&mut self.missed.borrow_mut(), key.to_string())
+    }
+
+    // true the first time a handle the driver never gave out is used
+    pub fn first_unknown(&self, handle: u32) -> bool {
+        first_report(&mut self.unknown.borrow_mut(), handle)
+    }
+
+    pub fn resident(&self) -> Vec<(&str, u32)> {
+        let mut resident: Vec<(&str, u32)> = self
+            .slots
+            .iter()
+            .filter_map(|slot| {
+                let slot = slot.as_ref()?;
+                slot.entry
+                    .as_ref()
+                    .map(|entry| (slot.key.as_str(), entry.refs))
+            }).collect();
+        resident.sort();
+        resident
+    }
+}
+
+// a key that keeps missing is reported again once the set has filled up,
+// rather than the set growing with every key an app ever churns through
+fn first_report<K: ::std::hash::Hash + Eq>(reported: &mut HashSet<K>, key: K) -> bool {
+    if reported.len() >= MAX_REPORTED && !reported.contains(&key) {
+        reported.clear();
+    }
+    reported.insert(key)
+}
+
+pub struct FontData {
+    pub id: i32,
+    // nanovg reads the font from this buffer for as long as it exists
+    #[allow(unused)]
+    data: Vec<u8>,
+}
+
+pub struct ResourceCache<'tx> {
+    pub textures: RefTable<Image<'tx>>,
+    pub fonts: RefTable<FontData>,
+    // nanovg has no way to delete a font, so released fonts keep their bytes
+    // here and come back without being added twice when loaded again
+    retired_fonts: HashMap<String, FontData>,
+}
+
+impl<'tx> ResourceCache<'tx> {
+    pub fn new() -> ResourceCache<'tx> {
+        ResourceCache {
+            textures: RefTable::new(),
+            fonts: RefTable::new(),
+            retired_fonts: Default::default(),
+        }
+    }
+
+    pub fn load(
+        &mut self,
+        ctx: &'tx NanoContext,
+        kind: u32,
+        key: &str,
+        data: Vec<u8>,
+    ) -> Result<u32, String> {
+        match kind {
+            RESOURCE_TEXTURE => self.textures.load(key, || create_texture(ctx, data)),
+            RESOURCE_FONT => {
+                let retired = &mut self.retired_fonts;
+                self.fonts.load(key, || match retired.remove(key) {
+                    Some(font) => Ok(font),
+                    None => create_font(ctx, key, data),
+                })
+            }
+            _ => Err(format!("unknown resource kind: {}", kind)),
+        }
+    }
+
+    pub fn release(&mut self, kind: u32, key: &str) -> Result<(), String> {
+        match kind {
+            RESOURCE_TEXTURE => {
+                self.textures.release(key);
+            }
+            RESOURCE_FONT => self.release_font(key),
+            _ => return Err(format!("unknown resource kind: {}", kind)),
+        }
+        Ok(())
+    }
+
+    pub fn put_font(&mut self, ctx: &'tx NanoContext, key: &str, data: Vec<u8>) -> Result<(), String> {
+        if !self.fonts.contains(key) {
+            let font = match self.retired_fonts.remove(key) {
+                Some(font) => font,
+                None => create_font(ctx, key, data)?,
+            };
+            self.fonts.put(key, font);
+        }
+        Ok(())
+    }
+
+    fn release_font(&mut self, key: &str) {
+        if let Some(font) = self.fonts.release(key) {
+            self.retired_fonts.insert(key.to_string(), font);
+        }
+    }
+
+    pub fn remove_font(&mut self, key: &str) {
+        if let Some(font) = self.fonts.remove(key) {
+            self.retired_fonts.insert(key.to_string(), font);
+        }
+    }
+
+    // reports each missing texture once until it is loaded
+    pub fn texture(&self, key: &str) -> Option<&Image<'tx>> {
+        let texture = self.textures.get(key);
+        if texture.is_none() && self.textures.first_miss(key) {
+            send_cache_miss(key.to_string());
+        }
+        texture
+    }
+
+    pub fn texture_by_handle(&self, handle: u32) -> Option<&Image<'tx>> {
+        match self.textures.get_by_handle(handle) {
+            Lookup::Found(texture) => Some(texture),
+            Lookup::Missing(key) => self.texture(key),
+            Lookup::Unknown => {
+                if self.textures.first_unknown(handle) {
+                    send_puts(format!("unknown texture handle: {}", handle));
+                }
+                None
+            }
+        }
+    }
+
+    pub fn font_id(&self, key: &str) -> Option<i32> {
+        let font = self.fonts.get(key).map(|font| font.id);
+        if font.is_none() && self.fonts.first_miss(key) {
+            send_font_miss(key.to_string());
+        }
+        font
+    }
+
+    pub fn font_id_by_handle(&self, handle: u32) -> Option<i32> {
+        match self.fonts.get_by_handle(handle) {
+            Lookup::Found(font) => Some(font.id),
+            Lookup::Missing(key) => self.font_id(key),
+            Lookup::Unknown => {
+                if self.fonts.first_unknown(handle) {
+                    send_puts(format!("unknown font handle: {}", handle));
+                }
+                None
+            }
+        }
+    }
+
+    // everything resident as (kind, key, references)
+    pub fn resident(&self) -> Vec<(u32, &str, u32)> {
+        let textures = self
+            .textures
+            .resident()
+            .into_iter()
+            .map(|(key, refs)| (RESOURCE_TEXTURE, key, refs));
+        let fonts = self
+            .fonts
+            .resident()
+            .into_iter()
+            .map(|(key, refs)| (RESOURCE_FONT, key, refs));
+        textures.chain(fonts).collect()
+    }
+}
+
+fn create_texture<'tx>(ctx: &'tx NanoContext, data: Vec<u8>) -> Result<Image<'tx>, String> {
+    Image::new(ctx)
+        .build_from_memory(&data)
+        .map_err(|err| format!("build image failed: {:?}", err))
+}
+
+// raw pixels come as 1 to 4 bytes each: gray, gray and alpha, RGB or RGBA.
+// nanovg only takes RGBA
+pub fn rgba_pixels(width: u32, height: u32, format: u32, data: &[u8]) -> Result<Vec<u32>, String> {
+    let channels = format as usize;
+    if !(1..=4).contains(&channels) {
+        return Err(format!("unknown pixel format: {}", format));
+    }
+    let count = width as usize * height as usize;
+    if data.len() != count * channels {
+        return Err(format!(
+            "expected {} bytes for {}x{}, got {}",
+            count * channels,
+            width,
+            height,
+            data.len()
+        ));
+    }
+    let pixels = data
+        .chunks(channels)
+        .map(|pixel| {
+            let rgba = match *pixel {
+                [gray] => [gray, gray, gray, 0xFF],
+                [gray, alpha] => [gray, gray, gray, alpha],
+                [red, green, blue] => [red, green, blue, 0xFF],
+                [red, green, blue, alpha] => [red, green, blue, alpha],
+                _ => unreachable!(),
+            };
+            u32::from_ne_bytes(rgba)
+        })
+        .collect();
+    Ok(pixels)
+}
+
+fn create_font(ctx: &NanoContext, key: &str, mut data: Vec<u8>) -> Result<FontData, String> {
+    let name = CString::new(key).map_err(|err| err.to_string())?;
+    let id = unsafe {
+        nvgCreateFontMem(
+            ctx.raw(),
+            name.as_ptr(),
+            data.as_mut_ptr(),
+            data.len() as i32,
+            0,
+        )
+    };
+    if id < 0 {
+        return Err(format!("load font failed: {}", key));
+    }
+    Ok(FontData { id: id, data: data })
+}
+
+#[test]
+fn rgba_pixels_test() {
+    let rgba = |bytes: [u8; 4]| u32::from_ne_bytes(bytes);
+    assert_eq!(
+        rgba_pixels(2, 1, 1, &[0x10, 0x20]).unwrap(),
+        vec![rgba([0x10, 0x10, 0x10, 0xFF]), rgba([0x20, 0x20, 0x20, 0xFF])]
+    );
+    assert_eq!(
+        rgba_pixels(1, 1, 2, &[0x10, 0x80]).unwrap(),
+        vec![rgba([0x10, 0x10, 0x10, 0x80])]
+    );
+    assert_eq!(
+        rgba_pixels(1, 1, 3, &[1, 2, 3]).unwrap(),
+        vec![rgba([1, 2, 3, 0xFF])]
+    );
+    assert_eq!(
+        rgba_pixels(1, 1, 4, &[1, 2, 3, 4]).unwrap(),
+        vec![rgba([1, 2, 3, 4])]
+    );
+    assert!(rgba_pixels(1, 1, 5, &[0; 5]).is_err());
+    assert!(rgba_pixels(2, 2, 4, &[0; 12]).is_err());
+}
+
+#[test]
+fn ref_table_test() {
+    let mut table: RefTable<u32> = RefTable::new();
+    assert!(table.first_miss("a"));
+    assert!(!table.first_miss("a"));
+
+    assert_eq!(table.load("a", || Ok::<u32, ()>(1)), Ok(1));
+    // already resident, the value is not built again
+    assert_eq!(table.load("a", || Err(())), Ok(2));
+    assert_eq!(table.get("a"), Some(&1));
+    assert!(!table.first_miss("a"));
+    assert_eq!(table.load("b", || Err("broken")), Err("broken"));
+    assert_eq!(table.put("c", 3), None);
+    assert_eq!(table.resident(), vec![("a", 2), ("c", 1)]);
+
+    assert_eq!(table.release("a"), None);
+    assert_eq!(table.release("a"), Some(1));
+    assert_eq!(table.release("a"), None);
+    assert_eq!(table.get("a"), None);
+    // missing again after the release, so it is reported again
+    assert!(table.first_miss("a"));
+
+    assert_eq!(table.remove("c"), Some(3));
+    assert!(table.resident().is_empty());
+}
+
+#[test]
+fn ref_table_handle_test() {
+    let mut table: RefTable<u32> = RefTable::new();
+    let a = table.handle("a");
+    assert_eq!(table.handle("a"), a);
+    let b = table.handle("b");
+    assert!(a != b);
+
+    match table.get_by_handle(a) {
+        Lookup::Missing(key) => assert_eq!(key, "a"),
+        _ => panic!("expected a miss"),
+    }
+    table.put("a", 7);
+    match table.get_by_handle(a) {
+        Lookup::Found(value) => assert_eq!(*value, 7),
+        _ => panic!("expected a hit"),
+    }
+    // the handle outlives the resource
+    table.remove("a");
+    assert_eq!(table.handle("a"), a);
+    assert!(match table.get_by_handle(99) {
+        Lookup::Unknown => true,
+        _ => false,
+    });
+    assert!(table.first_unknown(99));
+    assert!(!table.first_unknown(99));
+}
+
+#[test]
+fn ref_table_reuse_test() {
+    let mut table: RefTable<u32> = RefTable::new();
+    let kept = table.handle("kept");
+    table.put("kept", 1);
+    // keys that come and go without a handle share one slot
+    for n in 0..100 {
+        let key = format!("thumb{}", n);
+        table.load(&key, || Ok::<u32, ()>(n)).unwrap();
+        assert_eq!(table.release(&key), Some(n));
+    }
+    assert_eq!(table.slots.len(), 2);
+    assert!(table.handles.len() == 1 && table.handles.contains_key("kept"));
+
+    // a handed out handle keeps its key after the release
+    assert_eq!(table.release("kept"), Some(1));
+    assert_eq!(table.handle("kept"), kept);
+    table.put("other", 2);
+    assert!(match table.get_by_handle(kept) {
+        Lookup::Missing(key) => key == "kept",
+        _ => false,
+    });
+}
+
+#[test]
+fn first_report_test() {
+    let mut reported = HashSet::new();
+    for n in 0..MAX_REPORTED {
+        assert!(first_report(&mut reported, n));
+    }
+    assert!(!first_report(&mut reported, 0));
+    // full, so the next new key starts the reports over
+    assert!(first_report(&mut reported, MAX_REPORTED));
+    assert_eq!(reported.len(), 1);
+    assert!(first_report(&mut reported, 0));
+}
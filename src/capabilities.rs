@@ -0,0 +1,101 @@
+use crate::codec::SUPPORTED_CMDS;
+use crate::defines::*;
+use crate::ops::SUPPORTED_OPS;
+use std::io::Write;
+use crate::util::*;
+
+pub const DRIVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// one bit per code, codes are all below 0x100
+pub type CodeBitmap = [u32; 8];
+
+pub fn code_bitmap(codes: &[u32]) -> CodeBitmap {
+    let mut bitmap = [0u32; 8];
+    for code in codes {
+        let code = *code as usize & 0xFF;
+        bitmap[code / 32] |= 1 << (code % 32);
+    }
+    bitmap
+}
+
+// raw (unaccelerated) mouse motion needs GLFW 3.3, disabled cursors report
+// the platform's accelerated motion instead
+pub fn feature_bits() -> u32 {
+    0
+}
+
+pub fn transport_bits() -> u32 {
+    let mut bits = TRANSPORT_STDIO | TRANSPORT_TCP | TRANSPORT_REPLAY;
+    if cfg!(unix) {
+        bits |= TRANSPORT_UNIX;
+    }
+    bits
+}
+
+// the protocol version the caller launched us with must be one we speak,
+// otherwise the driver refuses to start
+pub fn check_protocol(version: u32) -> Result<(), String> {
+    if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION {
+        return Err(format!(
+            "protocol version {} is not supported, this driver speaks {} to {}",
+            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+    Ok(())
+}
+
+// MSG_OUT_CAPABILITIES body: protocol version, max texture size, transport
+// bits, feature bits, supported CMD_* bitmap, supported OP_* bitmap, then the
+// driver version as a length-prefixed string
+pub fn write_capabilities(mut w: &mut Write, max_texture_size: u32) -> ::std::io::Result<()> {
+    write_multi!(w, PROTOCOL_VERSION, max_texture_size, transport_bits(), feature_bits())?;
+    for word in code_bitmap(SUPPORTED_CMDS).iter() {
+        write_multi!(w, *word)?;
+    }
+    for word in code_bitmap(SUPPORTED_OPS).iter() {
+        write_multi!(w, *word)?;
+    }
+    write_multi!(w, DRIVER_VERSION.len() as u32)?;
+    w.write_all(DRIVER_VERSION.as_bytes())
+}
+
+#[test]
+fn code_bitmap_test() {
+    let bitmap = code_bitmap(&[0x00, 0x01, 0x21, 0xFE]);
+    assert_eq!(bitmap[0], 0b11);
+    assert_eq!(bitmap[1], 0b10);
+    assert_eq!(bitmap[7], 1 << 30);
+    assert_eq!(bitmap[2..7], [0u32; 5]);
+}
+
+#[test]
+fn check_protocol_test() {
+    assert!(check_protocol(PROTOCOL_VERSION).is_ok());
+    assert!(check_protocol(MIN_PROTOCOL_VERSION).is_ok());
+    assert!(check_protocol(PROTOCOL_VERSION + 1).is_err());
+    assert!(check_protocol(0).is_err());
+}
+
+#[test]
+fn write_capabilities_test() {
+    use byteorder::{NativeEndian, ReadBytesExt};
+    use std::io::Cursor;
+    let mut buf: Vec<u8> = vec![];
+    write_capabilities(&mut buf, 4096).unwrap();
+    let mut r = Cursor::new(buf);
+    let (version, max_texture_size, transports, features) =
+        read_multi!(r, u32, u32, u32, u32).unwrap();
+    assert_eq!(version, PROTOCOL_VERSION);
+    assert_eq!(max_texture_size, 4096);
+    assert_eq!(transports & TRANSPORT_STDIO, TRANSPORT_STDIO);
+    assert_eq!(features & FEATURE_RAW_MOUSE_MOTION, 0);
+    let mut cmds = [0u32; 8];
+    r.read_u32_into::<NativeEndian>(&mut cmds).unwrap();
+    assert_eq!(cmds, code_bitmap(SUPPORTED_CMDS));
+    assert!(cmds[0] & (1 << CMD_RENDER_GRAPH) != 0);
+    let mut ops = [0u32; 8];
+    r.read_u32_into::<NativeEndian>(&mut ops).unwrap();
+    assert!(ops[(OP_TERMINATE / 32) as usize] & (1 << (OP_TERMINATE % 32)) != 0);
+    let len = read_multi!(r, usize).unwrap();
+    assert_eq!(read_string(&mut r, len).unwrap(), DRIVER_VERSION);
+}
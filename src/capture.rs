@@ -0,0 +1,205 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::log::*;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::transport::*;
+
+// capture file layout: the magic, then one record per inbound message:
+// u64 microseconds since the capture started, u32 length, message bytes.
+// all big-endian like the port framing
+const CAPTURE_MAGIC: &[u8; 8] = b"SCNCAP01";
+
+pub struct CaptureWriter {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> io::Result<CaptureWriter> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(CAPTURE_MAGIC)?;
+        Ok(CaptureWriter {
+            out: out,
+            start: Instant::now(),
+        })
+    }
+
+    // flushed per message so a capture taken up to a crash is still usable
+    pub fn record(&mut self, msg: &[u8]) -> io::Result<()> {
+        let elapsed = self.start.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+        self.out.write_u64::<BigEndian>(micros)?;
+        self.out.write_u32::<BigEndian>(msg.len() as u32)?;
+        self.out.write_all(msg)?;
+        self.out.flush()
+    }
+}
+
+// a capture that cannot be created is reported and the driver runs without
+pub fn open_capture(path: &Path) -> Option<CaptureWriter> {
+    match CaptureWriter::create(path) {
+        Ok(capture) => Some(capture),
+        Err(err) => {
+            log(
+                LogLevel::Error,
+                format!("cannot capture to {}: {}", path.display(), err),
+            );
+            None
+        }
+    }
+}
+
+// plays a capture back as if it was arriving on the port, framed the same
+// way, at the recorded pace divided by `speed`
+pub struct ReplayReader<R> {
+    capture: R,
+    speed: f64,
+    start: Option<Instant>,
+    pending: io::Cursor<Vec<u8>>,
+    hold_at_end: bool,
+}
+
+impl<R: Read> ReplayReader<R> {
+    pub fn new(mut capture: R, speed: f64, hold_at_end: bool) -> io::Result<ReplayReader<R>> {
+        let mut magic = [0u8; 8];
+        capture.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a scenic capture file",
+            ));
+        }
+        Ok(ReplayReader {
+            capture: capture,
+            speed: speed,
+            start: None,
+            pending: io::Cursor::new(vec![]),
+            hold_at_end: hold_at_end,
+        })
+    }
+
+    fn next_record(&mut self) -> io::Result<bool> {
+        let micros = match self.capture.read_u64::<BigEndian>() {
+            Ok(micros) => micros,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let len = self.capture.read_u32::<BigEndian>()?;
+        let mut framed = Vec::with_capacity(len as usize + 4);
+        framed.write_u32::<BigEndian>(len)?;
+        self.capture
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut framed)?;
+        if framed.len() != len as usize + 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "capture ends in the middle of a message",
+            ));
+        }
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = Duration::from_micros((micros as f64 / self.speed) as u64);
+        let elapsed = start.elapsed();
+        if due > elapsed {
+            ::std::thread::sleep(due - elapsed);
+        }
+        self.pending = io::Cursor::new(framed);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for ReplayReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.position() as usize >= self.pending.get_ref().len()
+            && !self.next_record()?
+        {
+            if self.hold_at_end {
+                // keep the last frame up until the window is closed
+                loop {
+                    ::std::thread::park();
+                }
+            }
+            return Ok(0);
+        }
+        self.pending.read(buf)
+    }
+}
+
+pub struct Replay {
+    pub path: PathBuf,
+    pub speed: f64,
+}
+
+impl Transport for Replay {
+    fn describe(&self) -> String {
+        format!("replay:{}@{}", self.path.display(), self.speed)
+    }
+    // replies from the driver have nowhere to go during a replay
+    fn open(&self) -> io::Result<(Reader, Writer)> {
+        let file = BufReader::new(File::open(&self.path)?);
+        Ok((
+            Box::new(ReplayReader::new(file, self.speed, true)?),
+            Box::new(io::sink()),
+        ))
+    }
+}
+
+// "<path>" replays at the recorded pace, "<path>@4" four times faster
+pub fn parse_replay(spec: &str) -> Result<Replay, String> {
+    let (path, speed) = match spec.rfind('@') {
+        Some(at) => match spec[at + 1..].parse::<f64>() {
+            Ok(speed) => (&spec[..at], speed),
+            Err(_) => (spec, 1.0),
+        },
+        None => (spec, 1.0),
+    };
+    if path.is_empty() {
+        return Err("replay needs a capture file".to_string());
+    }
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!("invalid replay speed: {}", speed));
+    }
+    Ok(Replay {
+        path: PathBuf::from(path),
+        speed: speed,
+    })
+}
+
+#[test]
+fn capture_replay_test() {
+    let path = ::std::env::temp_dir().join(format!("scenic_capture_{}.cap", ::std::process::id()));
+    {
+        let mut capture = CaptureWriter::create(&path).unwrap();
+        capture.record(&[1, 2, 3]).unwrap();
+        capture.record(&[]).unwrap();
+        capture.record(&[4]).unwrap();
+    }
+    let file = File::open(&path).unwrap();
+    let mut replay = ReplayReader::new(file, 1000.0, false).unwrap();
+    assert_eq!(read_msg(&mut replay).unwrap(), vec![1, 2, 3]);
+    assert_eq!(read_msg(&mut replay).unwrap(), Vec::<u8>::new());
+    assert_eq!(read_msg(&mut replay).unwrap(), vec![4]);
+    assert!(read_msg(&mut replay).is_err());
+    ::std::fs::remove_file(&path).unwrap();
+
+    assert!(ReplayReader::new(&b"NOTACAPT"[..], 1.0, false).is_err());
+}
+
+#[test]
+fn parse_replay_test() {
+    let replay = parse_replay("/tmp/session.cap").unwrap();
+    assert_eq!(replay.path, PathBuf::from("/tmp/session.cap"));
+    assert_eq!(replay.speed, 1.0);
+    let replay = parse_replay("/tmp/session.cap@4").unwrap();
+    assert_eq!(replay.path, PathBuf::from("/tmp/session.cap"));
+    assert_eq!(replay.speed, 4.0);
+    assert_eq!(
+        parse_replay("/tmp/a@b.cap").unwrap().path,
+        PathBuf::from("/tmp/a@b.cap")
+    );
+    assert!(parse_replay("").is_err());
+    assert!(parse_replay("/tmp/session.cap@0").is_err());
+}
@@ -0,0 +1,829 @@
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crate::defines::*;
+use crate::error::ProtocolError;
+use std::io::{Cursor, Read, Write};
+use crate::types::*;
+use crate::util::*;
+
+type IOResult<T> = ::std::io::Result<T>;
+
+// the commands dispatch_message acts on, reported in MSG_OUT_CAPABILITIES
+pub const SUPPORTED_CMDS: &[u32] = &[
+    CMD_RENDER_GRAPH,
+    CMD_CLEAR_GRAPH,
+    CMD_SET_ROOT,
+    CMD_CACHE_LOAD,
+    CMD_CLEAR_COLOR,
+    CMD_UPDATE_GRAPH,
+    CMD_CACHE_RELEASE,
+    CMD_CACHE_QUERY,
+    CMD_INPUT,
+    CMD_CURSOR_MODE,
+    CMD_SET_CLIPBOARD,
+    CMD_GET_CLIPBOARD,
+    CMD_QUERY_MONITORS,
+    CMD_WINDOW,
+    CMD_QUIT,
+    CMD_QUERY_STATS,
+    CMD_RESHAPE,
+    CMD_POSITION,
+    CMD_FOCUS,
+    CMD_ICONIFY,
+    CMD_MAXIMIZE,
+    CMD_RESTORE,
+    CMD_SHOW,
+    CMD_HIDE,
+    CMD_HEARTBEAT,
+    CMD_FULLSCREEN,
+    CMD_SET_TITLE,
+    CMD_SET_ICON,
+    CMD_SET_CURSOR,
+    CMD_SET_CUSTOM_CURSOR,
+    CMD_NEW_TX_ID,
+    CMD_FREE_TX_ID,
+    CMD_PUT_TX_BLOB,
+    CMD_PUT_TX_RAW,
+    CMD_NEW_FONT_ID,
+    CMD_LOAD_FONT_FILE,
+    CMD_LOAD_FONT_BLOB,
+    CMD_FREE_FONT,
+    CMD_FREE_CURSOR,
+    CMD_SET_SIZE_LIMITS,
+    CMD_SET_ASPECT_RATIO,
+    CMD_NEW_WINDOW,
+    CMD_CLOSE_WINDOW,
+    CMD_CRASH,
+];
+
+// replaces `remove` ops of a stored script starting at op index `start`
+// with the ops encoded in `script`. an empty script deletes, a zero
+// `remove` inserts
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphPatch {
+    pub start: u32,
+    pub remove: u32,
+    pub script: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InboundCommand {
+    RenderGraph { id: u32, script: Vec<u8> },
+    ClearGraph { id: u32 },
+    SetRoot { id: i32 },
+    CacheLoad { kind: u32, key: String, data: Vec<u8> },
+    CacheRelease { kind: u32, key: String },
+    CacheQuery,
+    ClearColor { color: [u32; 4] },
+    UpdateGraph { id: u32, patches: Vec<GraphPatch> },
+    Input { flags: u32 },
+    CursorMode { mode: u32 },
+    SetClipboard { text: String },
+    GetClipboard { request_id: u32 },
+    QueryMonitors,
+    // `command` goes to window `id` instead of the main window
+    Window { id: u32, command: Box<InboundCommand> },
+    Quit,
+    QueryStats,
+    Reshape { width: i32, height: i32 },
+    Position { x: i32, y: i32 },
+    Focus,
+    Iconify,
+    Maximize,
+    Restore,
+    Show,
+    Hide,
+    Heartbeat { timeout_ms: u32 },
+    // mode 0 windowed, 1 exclusive, 2 borderless. zero width, height or
+    // refresh rate keep the monitor's current video mode
+    Fullscreen {
+        mode: u32,
+        monitor: u32,
+        width: u32,
+        height: u32,
+        refresh_rate: u32,
+    },
+    SetTitle { title: String },
+    // texture keys, one per icon size
+    SetIcon { keys: Vec<String> },
+    SetCursor { shape: u32 },
+    // empty pixels build the cursor from the texture `key`
+    SetCustomCursor {
+        key: String,
+        xhot: u32,
+        yhot: u32,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    NewTxId { key: String },
+    FreeTxId { key: String },
+    PutTxBlob { key: String, data: Vec<u8> },
+    PutTxRaw {
+        key: String,
+        width: u32,
+        height: u32,
+        format: u32,
+        data: Vec<u8>,
+    },
+    NewFontId { name: String },
+    LoadFontFile { name: String, path: String },
+    LoadFontBlob { name: String, data: Vec<u8> },
+    FreeFont { name: String },
+    FreeCursor { key: String },
+    // 0 leaves that limit off
+    SetSizeLimits {
+        min_width: u32,
+        min_height: u32,
+        max_width: u32,
+        max_height: u32,
+    },
+    // 0:0 lets the window take any shape again
+    SetAspectRatio { numer: u32, denom: u32 },
+    NewWindow {
+        id: u32,
+        width: i32,
+        height: i32,
+        resizable: bool,
+        title: String,
+    },
+    CloseWindow { id: u32 },
+    Crash,
+    Unknown { id: u32, payload: Vec<u8> },
+}
+
+impl InboundCommand {
+    pub fn id(&self) -> u32 {
+        use self::InboundCommand::*;
+        match self {
+            RenderGraph { .. } => CMD_RENDER_GRAPH,
+            ClearGraph { .. } => CMD_CLEAR_GRAPH,
+            SetRoot { .. } => CMD_SET_ROOT,
+            CacheLoad { .. } => CMD_CACHE_LOAD,
+            CacheRelease { .. } => CMD_CACHE_RELEASE,
+            CacheQuery => CMD_CACHE_QUERY,
+            ClearColor { .. } => CMD_CLEAR_COLOR,
+            UpdateGraph { .. } => CMD_UPDATE_GRAPH,
+            Input { .. } => CMD_INPUT,
+            CursorMode { .. } => CMD_CURSOR_MODE,
+            SetClipboard { .. } => CMD_SET_CLIPBOARD,
+            GetClipboard { .. } => CMD_GET_CLIPBOARD,
+            QueryMonitors => CMD_QUERY_MONITORS,
+            Window { .. } => CMD_WINDOW,
+            Quit => CMD_QUIT,
+            QueryStats => CMD_QUERY_STATS,
+            Reshape { .. } => CMD_RESHAPE,
+            Position { .. } => CMD_POSITION,
+            Focus => CMD_FOCUS,
+            Iconify => CMD_ICONIFY,
+            Maximize => CMD_MAXIMIZE,
+            Restore => CMD_RESTORE,
+            Show => CMD_SHOW,
+            Hide => CMD_HIDE,
+            Heartbeat { .. } => CMD_HEARTBEAT,
+            Fullscreen { .. } => CMD_FULLSCREEN,
+            SetTitle { .. } => CMD_SET_TITLE,
+            SetIcon { .. } => CMD_SET_ICON,
+            SetCursor { .. } => CMD_SET_CURSOR,
+            SetCustomCursor { .. } => CMD_SET_CUSTOM_CURSOR,
+            NewTxId { .. } => CMD_NEW_TX_ID,
+            FreeTxId { .. } => CMD_FREE_TX_ID,
+            PutTxBlob { .. } => CMD_PUT_TX_BLOB,
+            PutTxRaw { .. } => CMD_PUT_TX_RAW,
+            NewFontId { .. } => CMD_NEW_FONT_ID,
+            LoadFontFile { .. } => CMD_LOAD_FONT_FILE,
+            LoadFontBlob { .. } => CMD_LOAD_FONT_BLOB,
+            FreeFont { .. } => CMD_FREE_FONT,
+            FreeCursor { .. } => CMD_FREE_CURSOR,
+            SetSizeLimits { .. } => CMD_SET_SIZE_LIMITS,
+            SetAspectRatio { .. } => CMD_SET_ASPECT_RATIO,
+            NewWindow { .. } => CMD_NEW_WINDOW,
+            CloseWindow { .. } => CMD_CLOSE_WINDOW,
+            Crash => CMD_CRASH,
+            Unknown { id, .. } => *id,
+        }
+    }
+
+    // textures and fonts are shared by every window, so changing them
+    // redraws all of them
+    pub fn changes_resources(&self) -> bool {
+        use self::InboundCommand::*;
+        match self {
+            CacheLoad { .. } | CacheRelease { .. } | PutTxBlob { .. } | PutTxRaw { .. }
+            | FreeTxId { .. } | LoadFontFile { .. } | LoadFontBlob { .. } | FreeFont { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn decode(msg: &[u8]) -> Result<InboundCommand, ProtocolError> {
+        Self::decode_command(msg, false).map(|(cmd, _)| cmd)
+    }
+
+    // also returns the bytes after the command that nothing read
+    pub fn decode_with_rest(msg: &[u8]) -> Result<(InboundCommand, &[u8]), ProtocolError> {
+        Self::decode_command(msg, false).map(|(cmd, len)| (cmd, &msg[len..]))
+    }
+
+    fn decode_command(
+        msg: &[u8],
+        in_window: bool,
+    ) -> Result<(InboundCommand, usize), ProtocolError> {
+        let mut read = Cursor::new(msg);
+        let msg_id = read_multi!(read, u32)
+            .map_err(|err| ProtocolError::new(0, read.position(), err))?;
+        if msg_id == CMD_WINDOW {
+            if in_window {
+                return Err(ProtocolError::new(msg_id, 0, "nested window command"));
+            }
+            let id = read_multi!(read, u32)
+                .map_err(|err| ProtocolError::new(msg_id, read.position(), err))?;
+            // offsets in errors from the wrapped command count from its start
+            let start = read.position() as usize;
+            let (command, len) = Self::decode_command(&msg[start..], true)?;
+            let window = InboundCommand::Window {
+                id,
+                command: Box::new(command),
+            };
+            return Ok((window, start + len));
+        }
+        let cmd = Self::decode_body(msg_id, &mut read)
+            .map_err(|err| ProtocolError::new(msg_id, read.position(), err))?;
+        Ok((cmd, read.position() as usize))
+    }
+
+    fn decode_body(msg_id: u32, read: &mut Cursor<&[u8]>) -> IOResult<InboundCommand> {
+        use self::InboundCommand::*;
+        let cmd = match msg_id {
+            CMD_RENDER_GRAPH => {
+                let id = read_multi!(read, u32)?;
+                let mut script = vec![];
+                read.read_to_end(&mut script)?;
+                RenderGraph { id, script }
+            }
+            CMD_CLEAR_GRAPH => ClearGraph {
+                id: read_multi!(read, u32)?,
+            },
+            CMD_SET_ROOT => SetRoot {
+                id: read_multi!(read, i32)?,
+            },
+            CMD_CACHE_LOAD => {
+                let (kind, key_len, data_len) = read_multi!(read, u32, usize, usize)?;
+                let key = read_string(read, key_len)?;
+                let data = read_bytes(read, data_len)?;
+                CacheLoad { kind, key, data }
+            }
+            CMD_CACHE_RELEASE => {
+                let (kind, key_len) = read_multi!(read, u32, usize)?;
+                CacheRelease {
+                    kind,
+                    key: read_string(read, key_len)?,
+                }
+            }
+            CMD_CACHE_QUERY => CacheQuery,
+            CMD_CLEAR_COLOR => {
+                let mut color = [0u32; 4];
+                read.read_u32_into::<NativeEndian>(&mut color)?;
+                ClearColor { color }
+            }
+            CMD_UPDATE_GRAPH => {
+                let (id, count) = read_multi!(read, u32, u32)?;
+                let mut patches = vec![];
+                for _ in 0..count {
+                    let (start, remove, len) = read_multi!(read, u32, u32, usize)?;
+                    let script = read_bytes(read, len)?;
+                    patches.push(GraphPatch {
+                        start,
+                        remove,
+                        script,
+                    });
+                }
+                UpdateGraph { id, patches }
+            }
+            CMD_INPUT => Input {
+                flags: read_multi!(read, u32)?,
+            },
+            CMD_CURSOR_MODE => CursorMode {
+                mode: read_multi!(read, u32)?,
+            },
+            CMD_SET_CLIPBOARD => {
+                let len = read_multi!(read, usize)?;
+                SetClipboard {
+                    text: read_string(read, len)?,
+                }
+            }
+            CMD_GET_CLIPBOARD => GetClipboard {
+                request_id: read_multi!(read, u32)?,
+            },
+            CMD_QUERY_MONITORS => QueryMonitors,
+            CMD_QUIT => Quit,
+            CMD_QUERY_STATS => QueryStats,
+            CMD_RESHAPE => {
+                let (width, height) = read_multi!(read, i32, i32)?;
+                Reshape { width, height }
+            }
+            CMD_POSITION => {
+                let (x, y) = read_multi!(read, i32, i32)?;
+                Position { x, y }
+            }
+            CMD_FOCUS => Focus,
+            CMD_ICONIFY => Iconify,
+            CMD_MAXIMIZE => Maximize,
+            CMD_RESTORE => Restore,
+            CMD_SHOW => Show,
+            CMD_HIDE => Hide,
+            CMD_HEARTBEAT => Heartbeat {
+                timeout_ms: read_multi!(read, u32)?,
+            },
+            CMD_FULLSCREEN => {
+                let (mode, monitor, width, height, refresh_rate) =
+                    read_multi!(read, u32, u32, u32, u32, u32)?;
+                Fullscreen {
+                    mode,
+                    monitor,
+                    width,
+                    height,
+                    refresh_rate,
+                }
+            }
+            CMD_SET_TITLE => {
+                let len = read_multi!(read, usize)?;
+                SetTitle {
+                    title: read_string(read, len)?,
+                }
+            }
+            CMD_SET_ICON => {
+                let count = read_multi!(read, u32)?;
+                let mut keys = vec![];
+                for _ in 0..count {
+                    let len = read_multi!(read, usize)?;
+                    keys.push(read_string(read, len)?);
+                }
+                SetIcon { keys }
+            }
+            CMD_SET_CURSOR => SetCursor {
+                shape: read_multi!(read, u32)?,
+            },
+            CMD_SET_CUSTOM_CURSOR => {
+                let (key_len, xhot, yhot, width, height, pixels_len) =
+                    read_multi!(read, usize, u32, u32, u32, u32, usize)?;
+                let key = read_string(read, key_len)?;
+                let pixels = read_bytes(read, pixels_len)?;
+                SetCustomCursor {
+                    key,
+                    xhot,
+                    yhot,
+                    width,
+                    height,
+                    pixels,
+                }
+            }
+            CMD_NEW_TX_ID => {
+                let len = read_multi!(read, usize)?;
+                NewTxId {
+                    key: read_string(read, len)?,
+                }
+            }
+            CMD_FREE_TX_ID => {
+                let len = read_multi!(read, usize)?;
+                FreeTxId {
+                    key: read_string(read, len)?,
+                }
+            }
+            CMD_PUT_TX_BLOB => {
+                let (key_len, data_len) = read_multi!(read, usize, usize)?;
+                let key = read_string(read, key_len)?;
+                let data = read_bytes(read, data_len)?;
+                PutTxBlob { key, data }
+            }
+            CMD_PUT_TX_RAW => {
+                let (key_len, width, height, format) = read_multi!(read, usize, u32, u32, u32)?;
+                let key = read_string(read, key_len)?;
+                let mut data = vec![];
+                read.read_to_end(&mut data)?;
+                PutTxRaw {
+                    key,
+                    width,
+                    height,
+                    format,
+                    data,
+                }
+            }
+            CMD_NEW_FONT_ID => {
+                let len = read_multi!(read, usize)?;
+                NewFontId {
+                    name: read_string(read, len)?,
+                }
+            }
+            CMD_LOAD_FONT_FILE => {
+                let (name_len, path_len) = read_multi!(read, usize, usize)?;
+                let name = read_string(read, name_len)?;
+                let path = read_string(read, path_len)?;
+                LoadFontFile { name, path }
+            }
+            CMD_LOAD_FONT_BLOB => {
+                let (name_len, data_len) = read_multi!(read, usize, usize)?;
+                let name = read_string(read, name_len)?;
+                let data = read_bytes(read, data_len)?;
+                LoadFontBlob { name, data }
+            }
+            CMD_FREE_FONT => {
+                let len = read_multi!(read, usize)?;
+                FreeFont {
+                    name: read_string(read, len)?,
+                }
+            }
+            CMD_FREE_CURSOR => {
+                let len = read_multi!(read, usize)?;
+                FreeCursor {
+                    key: read_string(read, len)?,
+                }
+            }
+            CMD_SET_SIZE_LIMITS => {
+                let (min_width, min_height, max_width, max_height) =
+                    read_multi!(read, u32, u32, u32, u32)?;
+                SetSizeLimits {
+                    min_width,
+                    min_height,
+                    max_width,
+                    max_height,
+                }
+            }
+            CMD_SET_ASPECT_RATIO => {
+                let (numer, denom) = read_multi!(read, u32, u32)?;
+                SetAspectRatio { numer, denom }
+            }
+            CMD_NEW_WINDOW => {
+                let (id, width, height, resizable, len) =
+                    read_multi!(read, u32, i32, i32, bool, usize)?;
+                NewWindow {
+                    id,
+                    width,
+                    height,
+                    resizable,
+                    title: read_string(read, len)?,
+                }
+            }
+            CMD_CLOSE_WINDOW => CloseWindow {
+                id: read_multi!(read, u32)?,
+            },
+            CMD_CRASH => Crash,
+            id => {
+                let mut payload = vec![];
+                read.read_to_end(&mut payload)?;
+                Unknown { id, payload }
+            }
+        };
+        Ok(cmd)
+    }
+
+    #[allow(unused)]
+    pub fn encode(&self) -> Message {
+        let mut buf: Message = Vec::with_capacity(64);
+        self.encode_into(&mut buf)
+            .expect("writing to a Vec never fails");
+        buf
+    }
+
+    #[allow(unused)]
+    fn encode_into(&self, mut w: &mut Vec<u8>) -> IOResult<()> {
+        use self::InboundCommand::*;
+        write_multi!(w, self.id())?;
+        match self {
+            RenderGraph { id, script } => {
+                write_multi!(w, *id)?;
+                w.write_all(script)
+            }
+            ClearGraph { id } => write_multi!(w, *id),
+            SetRoot { id } => write_multi!(w, *id),
+            CacheLoad { kind, key, data } => {
+                write_multi!(w, *kind, key.len() as u32, data.len() as u32)?;
+                w.write_all(key.as_bytes())?;
+                w.write_all(data)
+            }
+            CacheRelease { kind, key } => {
+                write_multi!(w, *kind)?;
+                write_sized(w, key.as_bytes())
+            }
+            ClearColor { color } => write_multi!(w, color[0], color[1], color[2], color[3]),
+            UpdateGraph { id, patches } => {
+                write_multi!(w, *id, patches.len() as u32)?;
+                for patch in patches {
+                    write_multi!(w, patch.start, patch.remove)?;
+                    write_sized(w, &patch.script)?;
+                }
+                Ok(())
+            }
+            Input { flags } => write_multi!(w, *flags),
+            CursorMode { mode } => write_multi!(w, *mode),
+            SetClipboard { text } => write_sized(w, text.as_bytes()),
+            GetClipboard { request_id } => write_multi!(w, *request_id),
+            Reshape { width, height } => write_multi!(w, *width, *height),
+            Position { x, y } => write_multi!(w, *x, *y),
+            Heartbeat { timeout_ms } => write_multi!(w, *timeout_ms),
+            Fullscreen {
+                mode,
+                monitor,
+                width,
+                height,
+                refresh_rate,
+            } => write_multi!(w, *mode, *monitor, *width, *height, *refresh_rate),
+            SetTitle { title } => write_sized(w, title.as_bytes()),
+            SetCursor { shape } => write_multi!(w, *shape),
+            SetCustomCursor {
+                key,
+                xhot,
+                yhot,
+                width,
+                height,
+                pixels,
+            } => {
+                write_multi!(
+                    w,
+                    key.len() as u32,
+                    *xhot,
+                    *yhot,
+                    *width,
+                    *height,
+                    pixels.len() as u32
+                )?;
+                w.write_all(key.as_bytes())?;
+                w.write_all(pixels)
+            }
+            SetIcon { keys } => {
+                write_multi!(w, keys.len() as u32)?;
+                for key in keys {
+                    write_sized(w, key.as_bytes())?;
+                }
+                Ok(())
+            }
+            NewTxId { key } | FreeTxId { key } => write_sized(w, key.as_bytes()),
+            PutTxBlob { key, data } => {
+                write_multi!(w, key.len() as u32, data.len() as u32)?;
+                w.write_all(key.as_bytes())?;
+                w.write_all(data)
+            }
+            PutTxRaw {
+                key,
+                width,
+                height,
+                format,
+                data,
+            } => {
+                write_multi!(w, key.len() as u32, *width, *height, *format)?;
+                w.write_all(key.as_bytes())?;
+                w.write_all(data)
+            }
+            LoadFontFile { name, path } => {
+                write_multi!(w, name.len() as u32, path.len() as u32)?;
+                w.write_all(name.as_bytes())?;
+                w.write_all(path.as_bytes())
+            }
+            LoadFontBlob { name, data } => {
+                write_multi!(w, name.len() as u32, data.len() as u32)?;
+                w.write_all(name.as_bytes())?;
+                w.write_all(data)
+            }
+            NewFontId { name } | FreeFont { name } => write_sized(w, name.as_bytes()),
+            FreeCursor { key } => write_sized(w, key.as_bytes()),
+            SetSizeLimits {
+                min_width,
+                min_height,
+                max_width,
+                max_height,
+            } => write_multi!(w, *min_width, *min_height, *max_width, *max_height),
+            SetAspectRatio { numer, denom } => write_multi!(w, *numer, *denom),
+            Window { id, command } => {
+                write_multi!(w, *id)?;
+                command.encode_into(w)
+            }
+            NewWindow {
+                id,
+                width,
+                height,
+                resizable,
+                title,
+            } => {
+                write_multi!(w, *id, *width, *height, *resizable as u32)?;
+                write_sized(w, title.as_bytes())
+            }
+            CloseWindow { id } => write_multi!(w, *id),
+            Unknown { payload, .. } => w.write_all(payload),
+            CacheQuery | QueryMonitors | Quit | QueryStats | Focus | Iconify | Maximize | Restore
+            | Show | Hide | Crash => Ok(()),
+        }
+    }
+}
+
+#[allow(unused)]
+fn write_sized(w: &mut Vec<u8>, data: &[u8]) -> IOResult<()> {
+    w.write_u32::<NativeEndian>(data.len() as u32)?;
+    w.write_all(data)
+}
+
+#[test]
+fn codec_round_trip_test() {
+    use self::InboundCommand::*;
+    let commands = vec![
+        RenderGraph {
+            id: 7,
+            script: vec![OP_PATH_BEGIN as u8, 0, 0, 0, OP_TERMINATE as u8, 0, 0, 0],
+        },
+        ClearGraph { id: 7 },
+        SetRoot { id: -1 },
+        CacheLoad {
+            kind: RESOURCE_TEXTURE,
+            key: "tx_hash".to_string(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        },
+        CacheRelease {
+            kind: RESOURCE_FONT,
+            key: "font_hash".to_string(),
+        },
+        CacheQuery,
+        ClearColor {
+            color: [1, 2, 3, 255],
+        },
+        UpdateGraph {
+            id: 7,
+            patches: vec![
+                GraphPatch {
+                    start: 1,
+                    remove: 1,
+                    script: vec![OP_PATH_BEGIN as u8, 0, 0, 0],
+                },
+                GraphPatch {
+                    start: 0,
+                    remove: 2,
+                    script: vec![],
+                },
+            ],
+        },
+        Input { flags: 0xFFFF },
+        CursorMode { mode: 2 },
+        SetClipboard {
+            text: "copied \u{2022} text".to_string(),
+        },
+        GetClipboard { request_id: 42 },
+        QueryMonitors,
+        Quit,
+        QueryStats,
+        Reshape {
+            width: 800,
+            height: 600,
+        },
+        Position { x: -10, y: 20 },
+        Focus,
+        Iconify,
+        Maximize,
+        Restore,
+        Show,
+        Hide,
+        Heartbeat { timeout_ms: 5000 },
+        Fullscreen {
+            mode: 1,
+            monitor: 1,
+            width: 1920,
+            height: 1080,
+            refresh_rate: 60,
+        },
+        SetTitle {
+            title: "report.txt \u{2022} edited".to_string(),
+        },
+        SetIcon {
+            keys: vec!["icon_16".to_string(), "icon_32".to_string()],
+        },
+        SetCursor { shape: 2 },
+        SetCustomCursor {
+            key: "pointer".to_string(),
+            xhot: 1,
+            yhot: 0,
+            width: 1,
+            height: 1,
+            pixels: vec![255, 0, 0, 255],
+        },
+        NewTxId {
+            key: "tx_key".to_string(),
+        },
+        FreeTxId {
+            key: "tx_key".to_string(),
+        },
+        PutTxBlob {
+            key: "tx_key".to_string(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        },
+        PutTxRaw {
+            key: "raw".to_string(),
+            width: 2,
+            height: 1,
+            format: 4,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        },
+        NewFontId {
+            name: "roboto".to_string(),
+        },
+        LoadFontFile {
+            name: "roboto".to_string(),
+            path: "/fonts/Roboto-Regular.ttf".to_string(),
+        },
+        LoadFontBlob {
+            name: "roboto".to_string(),
+            data: vec![0, 1, 0, 0],
+        },
+        FreeFont {
+            name: "roboto".to_string(),
+        },
+        FreeCursor {
+            key: "pointer".to_string(),
+        },
+        SetSizeLimits {
+            min_width: 320,
+            min_height: 240,
+            max_width: 0,
+            max_height: 0,
+        },
+        SetAspectRatio {
+            numer: 16,
+            denom: 9,
+        },
+        Window {
+            id: 2,
+            command: Box::new(SetRoot { id: 5 }),
+        },
+        NewWindow {
+            id: 2,
+            width: 1920,
+            height: 1080,
+            resizable: true,
+            title: "wall 2".to_string(),
+        },
+        CloseWindow { id: 2 },
+        Crash,
+        Unknown {
+            id: 0xABCD,
+            payload: vec![1, 2, 3],
+        },
+    ];
+    for cmd in commands {
+        let msg = cmd.encode();
+        assert_eq!((&msg[0..4]).read_u32::<NativeEndian>().unwrap(), cmd.id());
+        assert_eq!(InboundCommand::decode(&msg).expect("decode failed"), cmd);
+    }
+}
+
+#[test]
+fn supported_cmds_decode_test() {
+    for cmd in SUPPORTED_CMDS {
+        let mut msg = vec![];
+        write_multi!(msg, *cmd, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32).unwrap();
+        match InboundCommand::decode(&msg) {
+            Ok(InboundCommand::Unknown { .. }) | Err(_) => panic!("cannot decode {:#X}", cmd),
+            Ok(decoded) => assert_eq!(decoded.id(), *cmd),
+        }
+    }
+}
+
+#[test]
+fn codec_truncated_test() {
+    let mut msg = InboundCommand::Reshape {
+        width: 800,
+        height: 600,
+    }.encode();
+    msg.truncate(6);
+    let err = InboundCommand::decode(&msg).unwrap_err();
+    assert_eq!(err.cmd, CMD_RESHAPE);
+    assert_eq!(err.offset, 6);
+    assert!(InboundCommand::decode(&[]).is_err());
+
+    let mut msg = vec![];
+    write_multi!(msg, CMD_FREE_TX_ID, 2u32).unwrap();
+    msg.extend_from_slice(&[0xff, 0xfe]);
+    let err = InboundCommand::decode(&msg).unwrap_err();
+    assert_eq!(err.cmd, CMD_FREE_TX_ID);
+    assert_eq!(err.offset, 10);
+
+    // a window command cannot wrap another one
+    let msg = InboundCommand::Window {
+        id: 1,
+        command: Box::new(InboundCommand::Window {
+            id: 2,
+            command: Box::new(InboundCommand::Quit),
+        }),
+    }.encode();
+    assert_eq!(InboundCommand::decode(&msg).unwrap_err().cmd, CMD_WINDOW);
+}
+
+#[test]
+fn codec_rest_test() {
+    let mut msg = InboundCommand::SetRoot { id: 3 }.encode();
+    msg.extend_from_slice(b"left over");
+    let (cmd, rest) = InboundCommand::decode_with_rest(&msg).unwrap();
+    assert_eq!(cmd, InboundCommand::SetRoot { id: 3 });
+    assert_eq!(rest, b"left over");
+
+    let mut msg = InboundCommand::Window {
+        id: 1,
+        command: Box::new(InboundCommand::SetRoot { id: 3 }),
+    }.encode();
+    msg.push(0);
+    let (_, rest) = InboundCommand::decode_with_rest(&msg).unwrap();
+    assert_eq!(rest, &[0]);
+}
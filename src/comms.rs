@@ -1,12 +1,25 @@
-use byteorder::{BigEndian, NativeEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{NativeEndian, WriteBytesExt};
+use crate::capabilities::write_capabilities;
+use crate::capture::CaptureWriter;
+use crate::codec::*;
+use crate::cursor::*;
 use crate::defines::*;
-use gl::*;
+use crate::display::*;
+use crate::error::ProtocolError;
+use crate::monitor::{connected_monitors, write_monitors, MonitorInfo};
+use crate::ops::{decode_script, OpSplice};
+use crate::outbound::{msg_id, Outbox};
 use glfw::Glfw;
-use std::io::{stdin, stdout, Cursor, Read, Write};
-use std::sync::mpsc::{Receiver, Sender};
+use std::cell::{Cell, RefCell};
+use crate::log::*;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::time::{Duration, Instant};
+use crate::transport::*;
 use crate::types::*;
 use crate::util::*;
+use crate::windows::*;
 
 type IOResult = ::std::io::Result<()>;
 
@@ -20,15 +33,30 @@ macro_rules! write_cmd_helper {
     };
 }
 
-pub fn send_reshape(window_width: i32, window_height: i32, frame_width: i32, frame_height: i32) {
+pub fn send_reshape(
+    window_width: i32,
+    window_height: i32,
+    frame_width: i32,
+    frame_height: i32,
+    xscale: f32,
+    yscale: f32,
+) {
     write_cmd_helper!(
         MSG_OUT_RESHAPE,
         window_width,
         window_height,
         frame_width,
-        frame_height
+        frame_height,
+        xscale,
+        yscale
     );
 }
+pub fn send_capabilities(max_texture_size: u32) {
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_CAPABILITIES)?;
+        write_capabilities(w, max_texture_size)
+    });
+}
 pub fn send_ready(root_id: i32) {
     write_cmd_helper!(MSG_OUT_READY, root_id)
 }
@@ -71,6 +99,9 @@ pub fn send_codepoint(codepoint: u32, mods: i32) {
 pub fn send_cursor_pos(xpos: f32, ypos: f32) {
     write_cmd_helper!(MSG_OUT_CURSOR_POS, xpos, ypos)
 }
+pub fn send_cursor_motion(dx: f32, dy: f32) {
+    write_cmd_helper!(MSG_OUT_CURSOR_MOTION, dx, dy)
+}
 pub fn send_mouse_button(button: i32, action: i32, mods: i32, xpos: f32, ypos: f32) {
     write_cmd_helper!(MSG_OUT_MOUSE_BUTTON, button, action, mods, xpos, ypos)
 }
@@ -80,194 +111,549 @@ pub fn send_scroll(xoffset: f32, yoffset: f32, xpos: f32, ypos: f32) {
 pub fn send_cursor_enter(entered: i32, xpos: f32, ypos: f32) {
     write_cmd_helper!(MSG_OUT_CURSOR_ENTER, entered, xpos, ypos)
 }
+// the cursor position, the number of paths, then each path length-prefixed
+pub fn send_drop_paths(paths: &[String], xpos: f32, ypos: f32) {
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_DROP_PATHS, xpos, ypos, paths.len() as u32)?;
+        for path in paths {
+            write_multi!(w, path.len() as u32)?;
+            w.write_all(path.as_bytes())?;
+        }
+        Ok(())
+    });
+}
+pub fn send_focus(focused: i32) {
+    write_cmd_helper!(MSG_OUT_FOCUS, focused)
+}
+pub fn send_iconify(iconified: i32) {
+    write_cmd_helper!(MSG_OUT_ICONIFY, iconified)
+}
+pub fn send_maximize(maximized: i32) {
+    write_cmd_helper!(MSG_OUT_MAXIMIZE, maximized)
+}
+pub fn send_window_pos(x: i32, y: i32) {
+    write_cmd_helper!(MSG_OUT_WINDOW_POS, x, y)
+}
+pub fn send_content_scale(xscale: f32, yscale: f32) {
+    write_cmd_helper!(MSG_OUT_CONTENT_SCALE, xscale, yscale)
+}
 pub fn send_close() {
     write_cmd_helper!(MSG_OUT_CLOSE)
 }
 pub fn send_draw_ready(id: u32) {
     write_cmd_helper!(MSG_OUT_DRAW_READY, id)
 }
+// the handle, then the key it stands for
+fn send_new_id(cmd: u32, handle: u32, key: String) {
+    write_cmd(|mut w| {
+        write_multi!(w, cmd, handle)?;
+        w.write_all(key.as_bytes())?;
+        Ok(())
+    });
+}
+// the request id, 1 if the clipboard held text and 0 if not, then the text
+pub fn send_clipboard(request_id: u32, text: Option<String>) {
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_CLIPBOARD, request_id, text.is_some() as u32)?;
+        w.write_all(text.unwrap_or_default().as_bytes())?;
+        Ok(())
+    });
+}
+pub fn send_monitors(monitors: &[MonitorInfo]) {
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_MONITORS)?;
+        write_monitors(w, monitors)
+    });
+}
+// 1 when connected and 0 when disconnected, then the monitor's name
+pub fn send_monitor_event(connected: u32, name: String) {
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_MONITOR_EVENT, connected)?;
+        w.write_all(name.as_bytes())?;
+        Ok(())
+    });
+}
+pub fn send_error(err: &ProtocolError) {
+    let script_id = err.script_id.map(|id| id as i32).unwrap_or(-1);
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_ERROR, err.cmd, script_id, err.offset as u32)?;
+        w.write_all(err.reason.as_bytes())?;
+        Ok(())
+    });
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Outbox> = RefCell::new(Outbox::new());
+}
+
+// every send_* after this is queued for the writer thread of the given
+// transport. the queue belongs to the main thread, which does all sending
+pub fn set_output(writer: Writer) {
+    OUTPUT.with(|output| output.borrow_mut().start(writer));
+}
+
+// hands everything queued since the last call to the writer thread, called
+// once per frame
+pub fn flush_output() {
+    OUTPUT.with(|output| output.borrow_mut().flush());
+}
+
+// replies and events that are never merged don't wait for the frame
+pub fn flush_replies() {
+    OUTPUT.with(|output| output.borrow_mut().flush_urgent());
+}
+
+// blocks until everything sent so far is written, before exiting
+pub fn close_output() {
+    OUTPUT.with(|output| output.borrow_mut().close());
+}
+
+pub fn coalesced_count() -> u32 {
+    OUTPUT.with(|output| output.borrow().coalesced)
+}
+
+thread_local! {
+    static WINDOW: Cell<u32> = Cell::new(MAIN_WINDOW);
+}
+
+// everything sent from `fun` is about window `id` and wrapped in
+// MSG_OUT_WINDOW unless that is the main window
+pub fn with_window<T>(id: u32, fun: impl FnOnce() -> T) -> T {
+    let outer = WINDOW.with(|window| window.replace(id));
+    let result = fun();
+    WINDOW.with(|window| window.set(outer));
+    result
+}
 
 pub fn write_cmd<F>(fun: F)
 where
     F: FnOnce(&mut Write) -> IOResult,
 {
     let mut buf: Vec<u8> = Vec::with_capacity(64);
+    let window = WINDOW.with(|window| window.get());
+    if window != MAIN_WINDOW {
+        write_multi!(buf, MSG_OUT_WINDOW, window).unwrap();
+    }
     fun(&mut buf).unwrap();
-    stdout().write_u32::<BigEndian>(buf.len() as u32).unwrap();
-    stdout().write_all(buf.as_slice()).unwrap();
-    stdout().flush().unwrap();
+    OUTPUT.with(|output| {
+        let mut output = output.borrow_mut();
+        if output.is_started() {
+            output.push(buf);
+        } else {
+            // before the transport is open, or from a thread other than main
+            log(
+                LogLevel::Error,
+                format!("no output for message {:#X}, dropped", msg_id(&buf).unwrap_or(0)),
+            );
+        }
+    });
 }
 
 const STD_TIMEOUT: u64 = 32000;
+// windows that need drawing after this have their redraw flag set
 pub fn handle_stdio_in<'ctx: 'tx, 'tx>(
-    window_data: &mut WindowData,
+    windows: &mut Windows,
     glfw: &mut Glfw,
     ctx: &mut Context<'ctx, 'tx>,
     receiver: &mut Receiver<Message>,
-) -> bool {
+) {
     let start = Instant::now();
     let timeout = Duration::from_micros(STD_TIMEOUT);
-    let mut redraw = false;
     while start.elapsed() < timeout {
-        if let Ok(msg) = receiver.try_recv() {
-            redraw = dispatch_message(window_data, msg, glfw, ctx) || redraw;
-        } else {
-            break;
+        match receiver.try_recv() {
+            Ok(msg) => {
+                windows.main().liveness.saw_message();
+                route_message(windows, msg, glfw, ctx);
+            }
+            Err(TryRecvError::Disconnected) => {
+                windows.main().liveness.input_closed();
+                break;
+            }
+            Err(TryRecvError::Empty) => break,
+        }
+    }
+}
+
+fn route_message<'ctx: 'tx, 'tx>(
+    windows: &mut Windows,
+    msg: Message,
+    glfw: &mut Glfw,
+    ctx: &mut Context<'ctx, 'tx>,
+) {
+    let (cmd, remain) = match InboundCommand::decode_with_rest(&msg) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            send_error(&err);
+            return;
+        }
+    };
+    let (id, cmd) = match cmd {
+        InboundCommand::NewWindow {
+            id,
+            width,
+            height,
+            resizable,
+            title,
+        } => {
+            if let Err(err) = windows.open(glfw, id, (width, height), resizable, &title) {
+                send_error(&ProtocolError::new(CMD_NEW_WINDOW, 0, err));
+            }
+            return;
+        }
+        InboundCommand::CloseWindow { id } => {
+            if let Err(err) = windows.close(id) {
+                send_error(&ProtocolError::new(CMD_CLOSE_WINDOW, 0, err));
+            }
+            return;
+        }
+        InboundCommand::Window { id, command } => match *command {
+            InboundCommand::NewWindow { .. } | InboundCommand::CloseWindow { .. } => {
+                send_error(&ProtocolError::new(
+                    CMD_WINDOW,
+                    0,
+                    "CMD_NEW_WINDOW and CMD_CLOSE_WINDOW cannot be wrapped",
+                ));
+                return;
+            }
+            // closes just that window, only the main one stops the driver
+            InboundCommand::Quit if id != MAIN_WINDOW => {
+                if let Err(err) = windows.close(id) {
+                    send_error(&ProtocolError::new(CMD_WINDOW, 0, err));
+                }
+                return;
+            }
+            command => (id, command),
+        },
+        cmd => (MAIN_WINDOW, cmd),
+    };
+    let changes_resources = cmd.changes_resources();
+    let window_data = match windows.get_mut(id) {
+        Some(window_data) => window_data,
+        None => {
+            send_error(&ProtocolError::new(CMD_WINDOW, 0, format!("no window {}", id)));
+            return;
+        }
+    };
+    let render = with_window(id, || {
+        let render = dispatch_message(window_data, cmd, glfw, ctx);
+        if !remain.is_empty() {
+            check_gl_error(String::from_utf8_lossy(remain).into_owned());
+        }
+        render
+    });
+    if render {
+        window_data.redraw = true;
+        if changes_resources {
+            windows.redraw_all();
         }
     }
-    redraw
 }
 
 fn dispatch_message<'ctx: 'tx, 'tx>(
     window_data: &mut WindowData,
-    msg: Message,
+    cmd: InboundCommand,
     glfw: &mut Glfw,
     ctx: &mut Context<'ctx, 'tx>,
 ) -> bool {
-    let mut read = Cursor::new(msg);
     let mut render = false;
-    let msg_id = read.read_u32::<NativeEndian>().unwrap();
     check_gl_error("starting error: ".to_string());
-    // send_puts(format!("dispatch message: {:#X}", msg_id));
-    match msg_id {
-        CMD_QUIT => {
+    // send_puts(format!("dispatch message: {:#X}", cmd.id()));
+    match cmd {
+        InboundCommand::Quit => {
             receive_quit(glfw, window_data);
             return false;
         }
-        CMD_RENDER_GRAPH => {
-            receive_render(glfw, &mut read, window_data);
+        InboundCommand::RenderGraph { id, script } => {
+            receive_render(glfw, window_data, id, script);
             render = true;
         }
-        CMD_CLEAR_GRAPH => {
-            receive_clear(glfw, &mut read, window_data);
+        InboundCommand::ClearGraph { id } => {
+            receive_clear(glfw, window_data, id);
             render = true;
         }
-        CMD_SET_ROOT => {
-            receive_set_root(glfw, &mut read, window_data);
+        InboundCommand::SetRoot { id } => {
+            receive_set_root(glfw, window_data, id);
             render = true;
         }
 
-        CMD_CLEAR_COLOR => {
-            receive_clear_color(glfw, &mut read);
+        InboundCommand::ClearColor { color } => {
+            receive_clear_color(glfw, window_data, color);
+            render = true;
+        }
+        InboundCommand::UpdateGraph { id, patches } => {
+            receive_update_graph(glfw, window_data, id, patches);
             render = true;
         }
-        /*
-     CMD_UPDATE_GRAPH=> {   receive_update_graph( &mut read, window );    render = false; }
-     CMD_CACHE_LOAD=> {     receive_cache_load( &mut read, window );      render = false; }
-     CMD_CACHE_RELEASE=> {  receive_cache_release( &mut read, window );   render = false; }
-*/
-        CMD_INPUT => receive_input(glfw, &mut read, window_data),
+        InboundCommand::CacheLoad { kind, key, data } => {
+            receive_cache_load(glfw, window_data, ctx, kind, key, data);
+            render = true;
+        }
+        InboundCommand::CacheRelease { kind, key } => {
+            receive_cache_release(glfw, window_data, ctx, kind, key)
+        }
+        InboundCommand::CacheQuery => receive_cache_query(glfw, window_data, ctx),
+        InboundCommand::Input { flags } => receive_input(glfw, window_data, flags),
+
+        InboundCommand::CursorMode { mode } => receive_cursor_mode(glfw, window_data, mode),
+
+        InboundCommand::SetClipboard { text } => window_data.window.set_clipboard_string(&text),
+
+        InboundCommand::GetClipboard { request_id } => {
+            send_clipboard(request_id, window_data.window.get_clipboard_string())
+        }
+
+        InboundCommand::QueryStats => receive_query_stats(glfw, window_data),
+
+        InboundCommand::QueryMonitors => send_monitors(&connected_monitors(glfw)),
+
+        InboundCommand::Reshape { width, height } => {
+            receive_reshape(glfw, window_data, width, height)
+        }
+
+        InboundCommand::Position { x, y } => receive_position(glfw, window_data, x, y),
+
+        InboundCommand::Focus => receive_focus(glfw, window_data),
+
+        InboundCommand::Iconify => window_data.window.iconify(),
+
+        InboundCommand::Maximize => receive_maximize(glfw, window_data),
+
+        InboundCommand::Restore => window_data.window.restore(),
+
+        InboundCommand::Show => window_data.window.show(),
+
+        InboundCommand::Hide => window_data.window.hide(),
+
+        InboundCommand::Heartbeat { timeout_ms } => {
+            receive_heartbeat(glfw, window_data, timeout_ms)
+        }
 
-        CMD_QUERY_STATS => receive_query_stats(glfw, window_data),
+        InboundCommand::Fullscreen {
+            mode,
+            monitor,
+            width,
+            height,
+            refresh_rate,
+        } => receive_fullscreen(glfw, window_data, mode, monitor, width, height, refresh_rate),
 
-        CMD_RESHAPE => receive_reshape(glfw, &mut read, window_data),
+        InboundCommand::SetTitle { title } => window_data.window.set_title(&title),
 
-        CMD_POSITION => receive_position(glfw, &mut read, window_data),
+        InboundCommand::SetIcon { keys } => receive_set_icon(glfw, window_data, ctx, keys),
 
-        //  CMD_FOCUS=> {          glfwFocusWindow( window );                      }
-        CMD_ICONIFY => window_data.window.iconify(),
+        InboundCommand::SetCursor { shape } => receive_set_cursor(glfw, window_data, shape),
 
-        //  CMD_MAXIMIZE=> {       glfwMaximizeWindow( window );                   }
-        CMD_RESTORE => window_data.window.restore(),
+        InboundCommand::SetCustomCursor {
+            key,
+            xhot,
+            yhot,
+            width,
+            height,
+            pixels,
+        } => receive_set_custom_cursor(
+            glfw,
+            window_data,
+            ctx,
+            key,
+            (xhot, yhot),
+            (width, height),
+            pixels,
+        ),
 
-        CMD_SHOW => window_data.window.show(),
+        InboundCommand::FreeCursor { key } => free_custom_cursor(window_data, key),
 
-        CMD_HIDE => window_data.window.hide(),
+        InboundCommand::SetSizeLimits {
+            min_width,
+            min_height,
+            max_width,
+            max_height,
+        } => receive_set_size_limits(
+            glfw,
+            window_data,
+            (min_width, min_height),
+            (max_width, max_height),
+        ),
 
-        //  CMD_NEW_DL_ID=> {      receive_new_dl_id();                            }
-    //  CMD_FREE_DL_ID=> {     receive_free_dl_id( &mut read );              render = true; }
-    //  CMD_NEW_TX_ID=> {      receive_new_tx_id();                            }
+        InboundCommand::SetAspectRatio { numer, denom } => {
+            receive_set_aspect_ratio(glfw, window_data, numer, denom)
+        }
 
+        InboundCommand::NewTxId { key } => receive_new_tx_id(glfw, window_data, ctx, key),
+        InboundCommand::NewFontId { name } => receive_new_font_id(glfw, window_data, ctx, name),
 
-    // font handling
-        CMD_LOAD_FONT_FILE => {
-            receive_load_font_file(glfw, &mut read, window_data, ctx);
+        InboundCommand::LoadFontFile { name, path } => {
+            receive_load_font_file(glfw, window_data, ctx, name, path);
             render = true;
         }
-        CMD_LOAD_FONT_BLOB => {
-            receive_load_font_blob(glfw, &mut read, window_data, ctx);
+        InboundCommand::LoadFontBlob { name, data } => {
+            receive_load_font_blob(glfw, window_data, ctx, name, data);
             render = true;
         }
-        //  CMD_FREE_FONT=> {      receive_free_font( &mut read, window );       }
+        InboundCommand::FreeFont { name } => receive_free_font(glfw, window_data, ctx, name),
 
-    // the next two are in texture.c
-        CMD_PUT_TX_BLOB => {
-            receive_put_tx_blob(glfw, &mut read, window_data, ctx);
+        InboundCommand::PutTxBlob { key, data } => {
+            receive_put_tx_blob(glfw, window_data, ctx, key, data);
+            render = true;
+        }
+        InboundCommand::PutTxRaw {
+            key,
+            width,
+            height,
+            format,
+            data,
+        } => {
+            receive_put_tx_raw(glfw, window_data, ctx, key, (width, height), format, data);
             render = true;
         }
-        //  CMD_PUT_TX_RAW=> {     receive_put_tx_raw( &mut read, window );      render = true; }
-        CMD_FREE_TX_ID => receive_free_tx_id(glfw, &mut read, window_data, ctx),
+        InboundCommand::FreeTxId { key } => receive_free_tx_id(glfw, window_data, ctx, key),
 
-        // the next set are in text.c
-        //  CMD_PUT_FONT=> {       receive_put_font_atlas( &mut read, window );  render = true; }
-        //  CMD_FREE_FONT_ID=> {   receive_free_font_atlas( &mut read, window ); render = true; }
-        CMD_CRASH => receive_crash(),
+        InboundCommand::Crash => receive_crash(),
 
-        _ => send_puts(format!("Unknown message: {:#X}", msg_id)),
-    }
-    let mut remain = String::new();
-    if read.read_to_string(&mut remain).unwrap() > 0 {
-        check_gl_error(remain);
+        cmd => send_puts(format!("Unknown message: {:#X}", cmd.id())),
     }
     render
 }
 
-fn read_msg_len() -> u32 {
-    stdin().read_u32::<BigEndian>().unwrap()
+pub fn start_read_thread(
+    mut reader: Reader,
+    mut capture: Option<CaptureWriter>,
+    sender: Sender<Message>,
+) {
+    // on EOF or a read error the thread ends and drops the sender, which is
+    // how the main loop learns that the Elixir side went away
+    ::std::thread::spawn(move || {
+        while let Ok(msg) = read_msg(&mut reader) {
+            if let Some(writer) = capture.as_mut() {
+                if writer.record(&msg).is_err() {
+                    // stop capturing rather than stop the driver
+                    capture = None;
+                }
+            }
+            if sender.send(msg).is_err() {
+                break;
+            }
+        }
+    });
 }
 
-pub fn start_read_stdin_thread(sender: Sender<Message>) {
-    ::std::thread::spawn(move || loop {
-        let len = read_msg_len() as usize;
-        let mut buf = vec![0u8; len];
-        stdin().read_exact(buf.as_mut_slice()).unwrap();
-        sender.send(buf).unwrap();
+fn receive_update_graph(
+    glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    id: u32,
+    patches: Vec<GraphPatch>,
+) {
+    let splices = patches
+        .into_iter()
+        .map(|patch| {
+            decode_script(&patch.script).map(|ops| OpSplice {
+                start: patch.start as usize,
+                remove: patch.remove as usize,
+                ops: ops,
+            })
+        }).collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.in_script(id));
+    match splices.and_then(|splices| window_data.patch_script(id, splices)) {
+        Ok(()) => {
+            send_draw_ready(id);
+            glfw.post_empty_event();
+        }
+        Err(err) => send_error(&err),
+    }
+}
+fn receive_cache_load<'ctx: 'tx, 'tx>(
+    glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context<'ctx, 'tx>,
+    kind: u32,
+    key: String,
+    data: Vec<u8>,
+) {
+    match ctx.load_resource(kind, key, data) {
+        Ok(_) => glfw.post_empty_event(),
+        Err(err) => send_error(&ProtocolError::new(CMD_CACHE_LOAD, 0, err)),
+    }
+}
+fn receive_cache_release(
+    _glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context,
+    kind: u32,
+    key: String,
+) {
+    if let Err(err) = ctx.release_resource(kind, key) {
+        send_error(&ProtocolError::new(CMD_CACHE_RELEASE, 0, err));
+    }
+}
+// MSG_OUT_CACHE_RESIDENT: entry count, then per entry the kind, the
+// reference count and the key as a length-prefixed string
+fn receive_cache_query(_glfw: &mut Glfw, _window_data: &mut WindowData, ctx: &mut Context) {
+    let resident = ctx.cache.resident();
+    write_cmd(|mut w| {
+        write_multi!(w, MSG_OUT_CACHE_RESIDENT, resident.len() as u32)?;
+        for (kind, key, refs) in &resident {
+            write_multi!(w, *kind, *refs, key.len() as u32)?;
+            w.write_all(key.as_bytes())?;
+        }
+        Ok(())
     });
 }
-
 fn receive_quit(glfw: &mut Glfw, window_data: &mut WindowData) {
     window_data.keep_going = false;
     glfw.post_empty_event();
 }
-fn receive_render(glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let id = read.read_u32::<NativeEndian>().unwrap();
-    let mut script = vec![];
-    read.read_to_end(&mut script).unwrap();
-    window_data.put_script(id, script);
-    send_draw_ready(id);
-    glfw.post_empty_event();
+fn receive_render(glfw: &mut Glfw, window_data: &mut WindowData, id: u32, script: Vec<u8>) {
+    match decode_script(&script) {
+        Ok(ops) => {
+            window_data.put_script(id, Rc::new(ops));
+            send_draw_ready(id);
+            glfw.post_empty_event();
+        }
+        Err(err) => {
+            // the old script under that id is not drawn in its place
+            window_data.delete_script(id);
+            send_error(&err.in_script(id));
+        }
+    }
 }
-fn receive_clear(_glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let id = read.read_u32::<NativeEndian>().unwrap();
+fn receive_clear(_glfw: &mut Glfw, window_data: &mut WindowData, id: u32) {
     window_data.delete_script(id);
 }
-fn receive_set_root(glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let id = read.read_i32::<NativeEndian>().unwrap();
+fn receive_set_root(glfw: &mut Glfw, window_data: &mut WindowData, id: i32) {
     window_data.root_script = id;
     glfw.post_empty_event();
 }
-fn receive_clear_color(_glfw: &mut Glfw, read: &mut impl Read) {
-    let mut color = [0u32; 4];
-    read.read_u32_into::<NativeEndian>(&mut color).unwrap();
-    unsafe {
-        ClearColor(
-            color[0] as f32 / 255.0f32,
-            color[1] as f32 / 255.0f32,
-            color[2] as f32 / 255.0f32,
-            color[3] as f32 / 255.0f32,
-        );
+fn receive_clear_color(_glfw: &mut Glfw, window_data: &mut WindowData, color: [u32; 4]) {
+    window_data.clear_color = [
+        color[0] as f32 / 255.0f32,
+        color[1] as f32 / 255.0f32,
+        color[2] as f32 / 255.0f32,
+        color[3] as f32 / 255.0f32,
+    ];
+}
+fn receive_input(_glfw: &mut Glfw, window_data: &mut WindowData, flags: u32) {
+    window_data.input_flags = flags;
+}
+fn receive_cursor_mode(_glfw: &mut Glfw, window_data: &mut WindowData, mode: u32) {
+    if let Err(err) = set_cursor_mode(window_data, mode) {
+        send_error(&ProtocolError::new(CMD_CURSOR_MODE, 0, err));
     }
 }
-// fn receive_update_graph(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-// fn receive_cache_load(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-// fn receive_cache_release(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-fn receive_input(_glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let flag = read.read_u32::<NativeEndian>().unwrap();
-    window_data.input_flags = flag;
+// a timeout of 0 turns the heartbeat check off again
+fn receive_heartbeat(_glfw: &mut Glfw, window_data: &mut WindowData, timeout_ms: u32) {
+    let timeout = match timeout_ms {
+        0 => None,
+        ms => Some(Duration::from_millis(ms as u64)),
+    };
+    window_data.liveness.set_heartbeat(timeout);
 }
 fn receive_query_stats(_glfw: &mut Glfw, window_data: &mut WindowData) {
     let (x, y) = window_data.window.get_pos();
     let (width, height) = window_data.window.get_size();
+    // GLFW has no getters for the limits and ratio, those are the last ones
+    // set. any that are not set go out as 0
+    let attribs = &window_data.attribs;
+    let (min_width, min_height) = attribs.min_size.unwrap_or((0, 0));
+    let (max_width, max_height) = attribs.max_size.unwrap_or((0, 0));
+    let (numer, denom) = attribs.aspect_ratio.unwrap_or((0, 0));
     write_cmd_helper!(
         MSG_OUT_STATS,
         window_data.input_flags,
@@ -279,74 +665,205 @@ fn receive_query_stats(_glfw: &mut Glfw, window_data: &mut WindowData) {
         window_data.window.is_resizable(),
         window_data.window.is_iconified(),
         window_data.window.is_maximized(),
-        window_data.window.is_visible()
+        window_data.window.is_visible(),
+        coalesced_count(),
+        is_floating(&window_data.window),
+        is_decorated(&window_data.window),
+        min_width,
+        min_height,
+        max_width,
+        max_height,
+        numer,
+        denom
     )
 }
-fn receive_reshape(_glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let (w, h) = read_multi!(read, i32, i32).unwrap();
+fn receive_focus(glfw: &mut Glfw, window_data: &mut WindowData) {
+    window_data.window.focus();
+    send_window_reshape(glfw, window_data);
+}
+fn receive_maximize(glfw: &mut Glfw, window_data: &mut WindowData) {
+    window_data.window.maximize();
+    send_window_reshape(glfw, window_data);
+}
+fn receive_fullscreen(
+    glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    mode: u32,
+    monitor: u32,
+    width: u32,
+    height: u32,
+    refresh_rate: u32,
+) {
+    let result = ScreenMode::from_u32(mode)
+        .ok_or_else(|| format!("unknown screen mode: {}", mode))
+        .and_then(|mode| {
+            let display_mode = DisplayMode {
+                mode: mode,
+                monitor: monitor as usize,
+                size: if width > 0 && height > 0 {
+                    Some((width, height))
+                } else {
+                    None
+                },
+                refresh_rate: if refresh_rate > 0 {
+                    Some(refresh_rate)
+                } else {
+                    None
+                },
+            };
+            set_display_mode(glfw, window_data, &display_mode)
+        });
+    if let Err(err) = result {
+        send_error(&ProtocolError::new(CMD_FULLSCREEN, 0, err));
+    }
+}
+fn receive_set_icon(
+    _glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    ctx: &mut Context,
+    keys: Vec<String>,
+) {
+    if let Err(err) = set_window_icon(window_data, ctx, &keys) {
+        send_error(&ProtocolError::new(CMD_SET_ICON, 0, err));
+    }
+}
+fn receive_set_cursor(_glfw: &mut Glfw, window_data: &mut WindowData, shape: u32) {
+    if let Err(err) = set_standard_cursor(window_data, shape) {
+        send_error(&ProtocolError::new(CMD_SET_CURSOR, 0, err));
+    }
+}
+fn receive_set_custom_cursor(
+    _glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    ctx: &mut Context,
+    key: String,
+    hotspot: (u32, u32),
+    size: (u32, u32),
+    pixels: Vec<u8>,
+) {
+    if let Err(err) = set_custom_cursor(window_data, ctx, key, hotspot, size, &pixels) {
+        send_error(&ProtocolError::new(CMD_SET_CUSTOM_CURSOR, 0, err));
+    }
+}
+// a width or height of 0 leaves that limit off
+fn receive_set_size_limits(
+    _glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    min_size: (u32, u32),
+    max_size: (u32, u32),
+) {
+    let limit = |size: (u32, u32)| match size {
+        (0, _) | (_, 0) => None,
+        size => Some(size),
+    };
+    set_size_limits(window_data, limit(min_size), limit(max_size));
+}
+fn receive_set_aspect_ratio(
+    _glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    numer: u32,
+    denom: u32,
+) {
+    if numer == 0 || denom == 0 {
+        set_aspect_ratio(window_data, None);
+    } else {
+        set_aspect_ratio(window_data, Some((numer, denom)));
+    }
+}
+fn receive_reshape(_glfw: &mut Glfw, window_data: &mut WindowData, w: i32, h: i32) {
     window_data.window.set_size(w, h);
 }
-fn receive_position(_glfw: &mut Glfw, read: &mut impl Read, window_data: &mut WindowData) {
-    let (x, y) = read_multi!(read, i32, i32).unwrap();
+fn receive_position(_glfw: &mut Glfw, window_data: &mut WindowData, x: i32, y: i32) {
     window_data.window.set_pos(x, y);
 }
 // fn receive_new_dl_id(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
 // fn receive_free_dl_id(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-// fn receive_new_tx_id(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
+// handles stay valid for the life of the driver, whether or not the
+// texture or font behind them is loaded yet
+fn receive_new_tx_id(
+    _glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context,
+    key: String,
+) {
+    let handle = ctx.cache.textures.handle(&key);
+    send_new_id(MSG_OUT_NEW_TX_ID, handle, key);
+}
+fn receive_new_font_id(
+    _glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context,
+    name: String,
+) {
+    let handle = ctx.cache.fonts.handle(&name);
+    send_new_id(MSG_OUT_NEW_FONT_ID, handle, name);
+}
 fn receive_load_font_file(
     _glfw: &mut Glfw,
-    read: &mut impl Read,
     _window_data: &mut WindowData,
     ctx: &mut Context,
+    name: String,
+    path: String,
 ) {
-    let (name_len, path_len) = read_multi!(read, usize, usize).unwrap();
-    let name = read_string(read, name_len);
-    let path = read_string(read, path_len);
-    if ::nanovg::Font::find(ctx.ctx, &name).is_err() {
-        ::nanovg::Font::from_file(ctx.ctx, &name, &path).unwrap();
+    let loaded = ::std::fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|data| ctx.put_font(name.clone(), data));
+    if let Err(err) = loaded {
+        send_puts(format!("load font file failed: {} {}: {}", name, path, err));
     }
 }
 fn receive_load_font_blob(
     _glfw: &mut Glfw,
-    read: &mut impl Read,
     _window_data: &mut WindowData,
     ctx: &mut Context,
+    name: String,
+    data: Vec<u8>,
 ) {
-    let (name_len, data_len) = read_multi!(read, usize, usize).unwrap();
-    let name = read_string(read, name_len);
-    let data = read_bytes(read, data_len);
-
-    if ::nanovg::Font::find(ctx.ctx, &name).is_err() {
-        ::nanovg::Font::from_memory(ctx.ctx, &name, &data).unwrap();
+    if let Err(err) = ctx.put_font(name.clone(), data) {
+        send_puts(format!("load font blob failed: {}: {}", name, err));
     }
 }
-// fn receive_free_font(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-// fn receive_put_tx_raw(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
+fn receive_free_font(
+    _glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context,
+    name: String,
+) {
+    ctx.free_font(name);
+}
 
 fn receive_put_tx_blob<'ctx: 'tx, 'tx>(
     _glfw: &mut Glfw,
-    read: &mut impl Read,
     _window_data: &mut WindowData,
     ctx: &mut Context<'ctx, 'tx>,
+    name: String,
+    data: Vec<u8>,
 ) {
-    let (name_len, data_len) = read_multi!(read, usize, usize).unwrap();
-    let name = read_string(read, name_len);
-    let data = read_bytes(read, data_len);
     ctx.put_tx(name, data);
 }
+fn receive_put_tx_raw<'ctx: 'tx, 'tx>(
+    _glfw: &mut Glfw,
+    _window_data: &mut WindowData,
+    ctx: &mut Context<'ctx, 'tx>,
+    name: String,
+    size: (u32, u32),
+    format: u32,
+    data: Vec<u8>,
+) {
+    if let Err(err) = ctx.put_tx_raw(name, size, format, &data) {
+        send_error(&ProtocolError::new(CMD_PUT_TX_RAW, 0, err));
+    }
+}
 fn receive_free_tx_id(
     _glfw: &mut Glfw,
-    read: &mut impl Read,
     _window_data: &mut WindowData,
     ctx: &mut Context,
+    name: String,
 ) {
-    let len = read_multi!(read, usize).unwrap();
-    let name = read_string(read, len);
     ctx.free_tx(name);
 }
-// fn receive_put_font_atlas(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
-// fn receive_free_font_atlas(_glfw:&mut  Glfw,window_data: &mut WindowData) {}
 fn receive_crash() {
     send_puts("receive_crash - exit".to_string());
+    close_output();
     ::std::process::exit(1);
 }
@@ -0,0 +1,560 @@
+use crate::capabilities::check_protocol;
+use crate::display::*;
+use crate::log::LogLevel;
+use std::path::{Path, PathBuf};
+use crate::transport::parse_transport;
+use crate::types::DEFAULT_MAX_SCRIPT_DEPTH;
+
+pub const CONFIG_ENV: &str = "SCENIC_DRIVER_CONFIG";
+const ENV_PREFIX: &str = "SCENIC_DRIVER_";
+
+// every option can be given as --name=value or --name value, as
+// SCENIC_DRIVER_NAME with dashes turned into underscores, or as a key in the
+// config file. the command line wins over the environment, which wins over
+// the file
+const OPTIONS: &[&str] = &[
+    "width",
+    "height",
+    "title",
+    "resizable",
+    "block-size",
+    "transport",
+    "log-level",
+    "frame-rate",
+    "msaa",
+    "vsync",
+    "fullscreen",
+    "monitor",
+    "hidden",
+    "capture",
+    "max-script-depth",
+    "window",
+    "protocol",
+];
+
+// a flag alone on the command line means true, so it never takes the next
+// argument as its value
+const FLAGS: &[&str] = &["resizable", "vsync", "hidden"];
+
+// what the Scenic.Driver.Glfw library passes, transport is optional
+const POSITIONAL: &[&str] = &[
+    "width",
+    "height",
+    "title",
+    "resizable",
+    "block-size",
+    "transport",
+];
+
+pub const USAGE: &str = "usage: scenic_driver_glfw [<width> <height> <title> <resizable> \
+<block_size> [<transport>]] [options]
+
+  --width=<pixels>            window width, 800
+  --height=<pixels>           window height, 600
+  --title=<title>             window title, Scenic
+  --resizable                 let the user resize the window
+  --block-size=<bytes>        read buffer for the port, 8192
+  --transport=<spec>          stdio, unix:<path>, tcp:<port> or replay:<file>[@speed]
+  --log-level=<level>         off, error, warn, info or debug, warn
+  --frame-rate=<fps>          most frames drawn per second, 60
+  --msaa=<samples>            multisampling, 0 for none
+  --vsync[=false]             wait for vertical sync, platform default if unset
+  --fullscreen=<spec>         windowed, exclusive or borderless, see the readme
+  --monitor=<index>           monitor to open on, 0 is the primary one
+  --hidden                    start hidden until CMD_SHOW
+  --capture=<file>            record every inbound message to a capture file
+  --max-script-depth=<depth>  deepest script nesting drawn, 64
+  --window=<attribs>          floating, undecorated, size limits and so on
+  --protocol=<version>        protocol version the caller speaks, refused if unsupported
+  --config=<file>             TOML or JSON file with any of the above
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub width: i32,
+    pub height: i32,
+    pub title: String,
+    pub resizable: bool,
+    pub block_size: usize,
+    pub transport: String,
+    pub log_level: LogLevel,
+    pub frame_rate: u32,
+    pub msaa: u32,
+    pub vsync: Option<bool>,
+    pub fullscreen: Option<DisplayMode>,
+    pub monitor: Option<usize>,
+    pub hidden: bool,
+    pub capture: Option<PathBuf>,
+    pub max_script_depth: usize,
+    pub window: WindowAttribs,
+    pub protocol: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            width: 800,
+            height: 600,
+            title: "Scenic".to_string(),
+            resizable: false,
+            block_size: 8192,
+            transport: "stdio".to_string(),
+            log_level: LogLevel::Warn,
+            frame_rate: 60,
+            msaa: 0,
+            vsync: None,
+            fullscreen: None,
+            monitor: None,
+            hidden: false,
+            capture: None,
+            max_script_depth: DEFAULT_MAX_SCRIPT_DEPTH,
+            window: WindowAttribs::default(),
+            protocol: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "width" => self.width = parse_number::<u32>(value, 1)? as i32,
+            "height" => self.height = parse_number::<u32>(value, 1)? as i32,
+            "title" => self.title = value.to_string(),
+            "resizable" => self.resizable = parse_bool(value)?,
+            "block-size" => self.block_size = parse_number(value, 1)?,
+            "transport" => {
+                parse_transport(value)?;
+                self.transport = value.to_string();
+            }
+            "log-level" => self.log_level = LogLevel::parse(value)?,
+            "frame-rate" => self.frame_rate = parse_number(value, 1)?,
+            "msaa" => self.msaa = parse_number(value, 0)?,
+            "vsync" => self.vsync = Some(parse_bool(value)?),
+            "fullscreen" => self.fullscreen = Some(parse_display_mode(value)?),
+            "monitor" => self.monitor = Some(parse_number(value, 0)?),
+            "hidden" => self.hidden = parse_bool(value)?,
+            "capture" => self.capture = Some(PathBuf::from(value)),
+            "max-script-depth" => self.max_script_depth = parse_number(value, 1)?,
+            "window" => self.window = parse_window_attribs(value)?,
+            "protocol" => {
+                let version = parse_number(value, 0)?;
+                check_protocol(version)?;
+                self.protocol = Some(version);
+            }
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+        Ok(())
+    }
+}
+
+pub enum Launch {
+    Run(Options),
+    Help,
+    // started by hand with nothing at all
+    NoArgs,
+}
+
+// `args` without the program name. `env` looks up an environment variable
+pub fn load_options(args: &[String], env: &Fn(&str) -> Option<String>) -> Result<Launch, String> {
+    if args.is_empty() {
+        return Ok(Launch::NoArgs);
+    }
+    let args = parse_args(args)?;
+    if args.iter().any(|(name, _)| name == "help") {
+        return Ok(Launch::Help);
+    }
+    let mut options = Options::default();
+
+    let config = match args.iter().rev().find(|(name, _)| name == "config") {
+        Some((_, path)) => Some(path.clone()),
+        None => env(CONFIG_ENV),
+    };
+    if let Some(path) = config {
+        for (key, value) in read_config(Path::new(&path))? {
+            options
+                .set(&key.replace('_', "-"), &value)
+                .map_err(|err| format!("{}: {}: {}", path, key, err))?;
+        }
+    }
+
+    for name in OPTIONS {
+        let var = env_var(name);
+        if let Some(value) = env(&var) {
+            options
+                .set(name, &value)
+                .map_err(|err| format!("{}: {}", var, err))?;
+        }
+    }
+
+    for (name, value) in args.iter().filter(|(name, _)| name != "config") {
+        options
+            .set(name, value)
+            .map_err(|err| format!("--{}: {}", name, err))?;
+    }
+
+    // --monitor picks the monitor for fullscreen too, whichever source set it
+    if let (Some(mode), Some(monitor)) = (options.fullscreen.as_mut(), options.monitor) {
+        mode.monitor = monitor;
+    }
+    Ok(Launch::Run(options))
+}
+
+fn env_var(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.replace('-', "_").to_uppercase())
+}
+
+// the command line as (name, value) pairs in order, the legacy positional
+// arguments included under their option names
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut positional = vec![];
+    let mut named = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+        let arg = &arg[2..];
+        let (name, value) = match arg.find('=') {
+            Some(eq) => (&arg[..eq], Some(arg[eq + 1..].to_string())),
+            None => (arg, None),
+        };
+        if name != "help" && name != "config" && !OPTIONS.contains(&name) {
+            return Err(format!("unknown option: --{}", name));
+        }
+        let value = match value {
+            Some(value) => value,
+            None if name == "help" || FLAGS.contains(&name) => "true".to_string(),
+            None => args
+                .next()
+                .cloned()
+                .ok_or_else(|| format!("--{} needs a value", name))?,
+        };
+        named.push((name.to_string(), value));
+    }
+    if !positional.is_empty() && positional.len() != 5 && positional.len() != 6 {
+        return Err(format!(
+            "expected <width> <height> <title> <resizable> <block_size> [<transport>], got {} \
+             positional arguments",
+            positional.len()
+        ));
+    }
+    let mut pairs: Vec<(String, String)> = POSITIONAL
+        .iter()
+        .zip(positional)
+        .map(|(name, value)| match *name {
+            // the library passes "true" or "false", anything else was false
+            "resizable" => (name.to_string(), (value == "true").to_string()),
+            _ => (name.to_string(), value),
+        })
+        .collect();
+    pairs.extend(named);
+    Ok(pairs)
+}
+
+fn parse_number<T>(value: &str, min: T) -> Result<T, String>
+where
+    T: ::std::str::FromStr + PartialOrd + ::std::fmt::Display,
+{
+    match value.trim().parse::<T>() {
+        Ok(number) if number >= min => Ok(number),
+        Ok(_) => Err(format!("must be at least {}: {}", min, value)),
+        Err(_) => Err(format!("not a number: {}", value)),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("not true or false: {}", value)),
+    }
+}
+
+// a flat JSON object, or TOML with one `key = value` per line. either way
+// the values are strings, numbers or booleans, returned as text
+pub fn parse_config(text: &str) -> Result<Vec<(String, String)>, String> {
+    if text.trim_start().starts_with('{') {
+        parse_json(text)
+    } else {
+        parse_toml(text)
+    }
+}
+
+fn read_config(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let text = ::std::fs::read_to_string(path)
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    parse_config(&text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn parse_toml(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut pairs = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |what: &str| format!("line {}: {}", number + 1, what);
+        let eq = line
+            .find('=')
+            .ok_or_else(|| invalid("expected key = value"))?;
+        let key = line[..eq].trim();
+        if key.is_empty() || key.starts_with('[') {
+            return Err(invalid("expected key = value"));
+        }
+        let rest = line[eq + 1..].trim();
+        let (value, rest) = if rest.starts_with('"') {
+            let mut chars = rest[1..].chars();
+            let value = parse_quoted(&mut chars).ok_or_else(|| invalid("unterminated string"))?;
+            (value, chars.as_str().trim().to_string())
+        } else if rest.starts_with('\'') {
+            // a literal string, no escapes
+            let end = rest[1..]
+                .find('\'')
+                .ok_or_else(|| invalid("unterminated string"))?;
+            (
+                rest[1..end + 1].to_string(),
+                rest[end + 2..].trim().to_string(),
+            )
+        } else {
+            // a bare number or boolean runs up to the first space
+            let end = rest
+                .find(|c: char| c == '#' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            (rest[..end].to_string(), rest[end..].trim().to_string())
+        };
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(invalid("unexpected text after the value"));
+        }
+        if value.is_empty() {
+            return Err(invalid("missing value"));
+        }
+        pairs.push((key.to_string(), value));
+    }
+    Ok(pairs)
+}
+
+fn parse_json(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = text.trim().chars();
+    let invalid =
+        || "invalid JSON, expected an object of strings, numbers and booleans".to_string();
+    let mut pairs = vec![];
+    let next = |chars: &mut ::std::str::Chars| {
+        *chars = chars.as_str().trim_start().chars();
+        chars.next()
+    };
+    if next(&mut chars) != Some('{') {
+        return Err(invalid());
+    }
+    loop {
+        match next(&mut chars) {
+            Some('}') if pairs.is_empty() => break,
+            Some('"') => (),
+            _ => return Err(invalid()),
+        }
+        let key = parse_quoted(&mut chars).ok_or_else(invalid)?;
+        if next(&mut chars) != Some(':') {
+            return Err(invalid());
+        }
+        let value = match next(&mut chars) {
+            Some('"') => parse_quoted(&mut chars).ok_or_else(invalid)?,
+            Some(first) => {
+                let mut value = first.to_string();
+                let rest = chars.as_str();
+                let end = rest
+                    .find(|c: char| c == ',' || c == '}' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                value.push_str(&rest[..end]);
+                chars = rest[end..].chars();
+                value
+            }
+            None => return Err(invalid()),
+        };
+        if value.is_empty() || value == "null" || value.starts_with(&['{', '['][..]) {
+            return Err(format!("{}: expected a string, number or boolean", key));
+        }
+        pairs.push((key, value));
+        match next(&mut chars) {
+            Some(',') => (),
+            Some('}') => break,
+            _ => return Err(invalid()),
+        }
+    }
+    if !chars.as_str().trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(pairs)
+}
+
+// the rest of a string whose opening quote was already taken, with the
+// escapes TOML and JSON have in common
+fn parse_quoted(chars: &mut ::std::str::Chars) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                '/' => value.push('/'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).ok()?;
+                    value.push(::std::char::from_u32(code)?);
+                }
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+fn load_test_options(args: &[&str], env: &[(&str, &str)]) -> Result<Options, String> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let env = |name: &str| {
+        env.iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.to_string())
+    };
+    match load_options(&args, &env)? {
+        Launch::Run(options) => Ok(options),
+        _ => Err("not run".to_string()),
+    }
+}
+
+#[test]
+fn load_options_test() {
+    // the form the library launches with
+    let options = load_test_options(&["1024", "768", "My App", "true", "4096"], &[]).unwrap();
+    assert_eq!((options.width, options.height), (1024, 768));
+    assert_eq!(options.title, "My App");
+    assert!(options.resizable);
+    assert_eq!(options.block_size, 4096);
+    assert_eq!(options.transport, "stdio");
+
+    let options = load_test_options(
+        &[
+            "1024",
+            "768",
+            "App",
+            "false",
+            "4096",
+            "tcp:4000",
+            "--msaa",
+            "4",
+            "--vsync",
+            "--frame-rate=30",
+            "--fullscreen=borderless",
+            "--monitor=1",
+        ],
+        &[],
+    )
+    .unwrap();
+    assert!(!options.resizable);
+    assert_eq!(options.transport, "tcp:4000");
+    assert_eq!(options.msaa, 4);
+    assert_eq!(options.vsync, Some(true));
+    assert_eq!(options.frame_rate, 30);
+    assert_eq!(options.fullscreen.unwrap().monitor, 1);
+
+    // named options alone, the environment loses to the command line
+    let options = load_test_options(
+        &["--width=640", "--hidden"],
+        &[
+            ("SCENIC_DRIVER_WIDTH", "320"),
+            ("SCENIC_DRIVER_LOG_LEVEL", "debug"),
+            ("SCENIC_DRIVER_CAPTURE", "/tmp/session.cap"),
+            ("SCENIC_DRIVER_WINDOW", "floating"),
+        ],
+    )
+    .unwrap();
+    assert_eq!((options.width, options.height), (640, 600));
+    assert!(options.hidden);
+    assert_eq!(options.log_level, LogLevel::Debug);
+    assert_eq!(options.capture, Some(PathBuf::from("/tmp/session.cap")));
+    assert!(options.window.floating);
+
+    assert!(load_test_options(&["800", "600"], &[]).is_err());
+    assert!(load_test_options(&["--width=-5"], &[]).is_err());
+    assert!(load_test_options(&["--width=wide"], &[]).is_err());
+    assert!(load_test_options(&["--frame-rate=0"], &[]).is_err());
+    assert!(load_test_options(&["--colour=red"], &[]).is_err());
+    assert!(load_test_options(&["--transport=pipe"], &[]).is_err());
+    assert!(load_test_options(&["--title"], &[]).is_err());
+    assert!(load_test_options(&["--hidden=maybe"], &[]).is_err());
+    assert!(load_test_options(&["--protocol=999"], &[]).is_err());
+    let options = load_test_options(&["--protocol=1"], &[]).unwrap();
+    assert_eq!(options.protocol, Some(1));
+    let err = load_test_options(&["--vsync"], &[("SCENIC_DRIVER_FULLSCREEN", "huge")]);
+    assert!(err.unwrap_err().starts_with("SCENIC_DRIVER_FULLSCREEN: "));
+}
+
+#[test]
+fn config_file_test() {
+    let path = ::std::env::temp_dir().join(format!("scenic_config_{}.toml", ::std::process::id()));
+    ::std::fs::write(&path, "title = \"From file\"\nwidth = 1280\nmsaa = 2\n").unwrap();
+    let config = path.to_str().unwrap();
+    let options = load_test_options(
+        &["--msaa=8", "--config", config],
+        &[("SCENIC_DRIVER_WIDTH", "1024")],
+    )
+    .unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+    assert_eq!(options.title, "From file");
+    assert_eq!(options.width, 1024);
+    assert_eq!(options.msaa, 8);
+    assert!(load_test_options(&["--config=/nonexistent/scenic.toml"], &[]).is_err());
+}
+
+#[test]
+fn parse_config_test() {
+    let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    };
+    let toml = "
+        # comments and blank lines are skipped
+
+        width = 1024
+        title = \"Say \\\"hi\\\" # not a comment\"
+        vsync = true  # trailing comment
+        log_level = 'debug'
+    ";
+    assert_eq!(
+        parse_config(toml).unwrap(),
+        pairs(&[
+            ("width", "1024"),
+            ("title", "Say \"hi\" # not a comment"),
+            ("vsync", "true"),
+            ("log_level", "debug"),
+        ])
+    );
+    let json = r#" {"width": 1024, "title": "Caf\u00e9\n", "vsync":false} "#;
+    assert_eq!(
+        parse_config(json).unwrap(),
+        pairs(&[
+            ("width", "1024"),
+            ("title", "Caf\u{e9}\n"),
+            ("vsync", "false")
+        ])
+    );
+    assert_eq!(parse_config("{}").unwrap(), pairs(&[]));
+
+    assert_eq!(
+        parse_config("[window]\n").unwrap_err(),
+        "line 1: expected key = value"
+    );
+    assert!(parse_config("title = \"open").is_err());
+    assert!(parse_config("width = 10 20").is_err());
+    assert!(parse_config("width =").is_err());
+    assert!(parse_config(r#"{"width": 1024"#).is_err());
+    assert!(parse_config(r#"{"window": {"floating": true}}"#).is_err());
+    assert!(parse_config(r#"{"width": 1024,}"#).is_err());
+    assert!(parse_config(r#"{"monitor": null}"#).is_err());
+}
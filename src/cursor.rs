@@ -0,0 +1,229 @@
+use byteorder::{NativeEndian, ReadBytesExt};
+use crate::display::read_pixels;
+use glfw::{Cursor, CursorMode, PixelImage, StandardCursor};
+use std::collections::HashMap;
+use crate::types::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CursorKey {
+    Standard(StandardCursor),
+    Custom(String),
+}
+
+// CMD_SET_CURSOR shapes, 0 goes back to the platform default
+pub fn standard_cursor(shape: u32) -> Result<Option<StandardCursor>, String> {
+    match shape {
+        0 => Ok(None),
+        1 => Ok(Some(StandardCursor::Arrow)),
+        2 => Ok(Some(StandardCursor::IBeam)),
+        3 => Ok(Some(StandardCursor::Crosshair)),
+        4 => Ok(Some(StandardCursor::Hand)),
+        5 => Ok(Some(StandardCursor::HResize)),
+        6 => Ok(Some(StandardCursor::VResize)),
+        _ => Err(format!("unknown cursor shape: {}", shape)),
+    }
+}
+
+// glfw hands the active cursor to the window, so the cache holds every
+// cursor built so far except that one and swaps them through `set`, which
+// takes the new cursor and returns the one it replaced
+pub struct CursorCache<C> {
+    cursors: HashMap<CursorKey, C>,
+    current: Option<CursorKey>,
+}
+
+impl<C> CursorCache<C> {
+    pub fn new() -> CursorCache<C> {
+        CursorCache {
+            cursors: Default::default(),
+            current: None,
+        }
+    }
+
+    pub fn contains(&self, key: &CursorKey) -> bool {
+        self.current.as_ref() == Some(key) || self.cursors.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: CursorKey, cursor: C) {
+        self.cursors.insert(key, cursor);
+    }
+
+    pub fn select(
+        &mut self,
+        key: Option<CursorKey>,
+        set: impl FnOnce(Option<C>) -> Option<C>,
+    ) -> Result<(), String> {
+        if key == self.current {
+            return Ok(());
+        }
+        let cursor = match &key {
+            Some(key) => Some(
+                self.cursors
+                    .remove(key)
+                    .ok_or_else(|| format!("no cursor {:?}", key))?,
+            ),
+            None => None,
+        };
+        let previous = set(cursor);
+        if let (Some(current), Some(previous)) = (self.current.take(), previous) {
+            self.cursors.insert(current, previous);
+        }
+        self.current = key;
+        Ok(())
+    }
+
+    // a custom cursor that is showing is replaced by the default one first
+    pub fn free(&mut self, key: &CursorKey, set: impl FnOnce(Option<C>) -> Option<C>) {
+        if self.current.as_ref() == Some(key) {
+            set(None);
+            self.current = None;
+        } else {
+            self.cursors.remove(key);
+        }
+    }
+}
+
+// CMD_CURSOR_MODE modes
+pub fn cursor_mode(mode: u32) -> Result<CursorMode, String> {
+    match mode {
+        0 => Ok(CursorMode::Normal),
+        1 => Ok(CursorMode::Hidden),
+        2 => Ok(CursorMode::Disabled),
+        _ => Err(format!("unknown cursor mode: {}", mode)),
+    }
+}
+
+// a disabled cursor is captured by the window and reported as relative
+// motion. GLFW 3.2 has no raw motion, so the platform's acceleration applies
+pub fn set_cursor_mode(window_data: &mut WindowData, mode: u32) -> Result<(), String> {
+    let mode = cursor_mode(mode)?;
+    window_data.window.set_cursor_mode(mode);
+    // motion is measured from where the cursor is after the switch
+    window_data.last_cursor_pos = window_data.get_cursor_pos();
+    window_data.cursor_mode = mode;
+    Ok(())
+}
+
+pub fn set_standard_cursor(window_data: &mut WindowData, shape: u32) -> Result<(), String> {
+    let shape = standard_cursor(shape)?;
+    if let Some(shape) = shape {
+        let key = CursorKey::Standard(shape);
+        if !window_data.cursors.contains(&key) {
+            window_data.cursors.insert(key, Cursor::standard(shape));
+        }
+    }
+    select_cursor(window_data, shape.map(CursorKey::Standard))
+}
+
+// built from `width` x `height` RGBA pixels, or from the texture `key` when
+// no pixels are given. a cursor already built for `key` is reused as is
+pub fn set_custom_cursor(
+    window_data: &mut WindowData,
+    ctx: &Context,
+    key: String,
+    hotspot: (u32, u32),
+    size: (u32, u32),
+    pixels: &[u8],
+) -> Result<(), String> {
+    let cursor_key = CursorKey::Custom(key.clone());
+    if !window_data.cursors.contains(&cursor_key) {
+        let image = if pixels.is_empty() {
+            let texture = ctx
+                .cache
+                .textures
+                .get(&key)
+                .ok_or_else(|| format!("no texture: {}", key))?;
+            read_pixels(ctx.ctx, texture)
+        } else {
+            pixel_image(size, pixels)?
+        };
+        window_data.cursors.insert(
+            cursor_key.clone(),
+            Cursor::create_from_pixels(image, hotspot.0, hotspot.1),
+        );
+    }
+    select_cursor(window_data, Some(cursor_key))
+}
+
+pub fn free_custom_cursor(window_data: &mut WindowData, key: String) {
+    let window = &mut window_data.window;
+    window_data
+        .cursors
+        .free(&CursorKey::Custom(key), |cursor| window.set_cursor(cursor));
+}
+
+fn select_cursor(window_data: &mut WindowData, key: Option<CursorKey>) -> Result<(), String> {
+    let window = &mut window_data.window;
+    window_data
+        .cursors
+        .select(key, |cursor| window.set_cursor(cursor))
+}
+
+fn pixel_image(size: (u32, u32), mut pixels: &[u8]) -> Result<PixelImage, String> {
+    let (width, height) = size;
+    let count = width as usize * height as usize;
+    if count == 0 || pixels.len() != count * 4 {
+        return Err(format!(
+            "{} bytes of pixels for a {}x{} cursor",
+            pixels.len(),
+            width,
+            height
+        ));
+    }
+    let mut rgba = vec![0u32; count];
+    pixels
+        .read_u32_into::<NativeEndian>(&mut rgba)
+        .map_err(|err| err.to_string())?;
+    Ok(PixelImage {
+        width: width,
+        height: height,
+        pixels: rgba,
+    })
+}
+
+#[test]
+fn cursor_cache_test() {
+    let mut cache: CursorCache<u32> = CursorCache::new();
+    let mut window: Option<u32> = None;
+    let ibeam = CursorKey::Standard(StandardCursor::IBeam);
+    let custom = CursorKey::Custom("pointer".to_string());
+
+    assert!(cache
+        .select(Some(ibeam.clone()), |c| ::std::mem::replace(&mut window, c))
+        .is_err());
+    cache.insert(ibeam.clone(), 1);
+    cache.insert(custom.clone(), 2);
+    cache
+        .select(Some(ibeam.clone()), |c| ::std::mem::replace(&mut window, c))
+        .unwrap();
+    assert_eq!(window, Some(1));
+    cache
+        .select(Some(custom.clone()), |c| ::std::mem::replace(&mut window, c))
+        .unwrap();
+    assert_eq!(window, Some(2));
+    // the ibeam went back into the cache when it was replaced
+    assert!(cache.contains(&ibeam));
+    cache
+        .select(None, |c| ::std::mem::replace(&mut window, c))
+        .unwrap();
+    assert_eq!(window, None);
+    assert!(cache.contains(&custom));
+
+    cache
+        .select(Some(custom.clone()), |c| ::std::mem::replace(&mut window, c))
+        .unwrap();
+    cache.free(&custom, |c| ::std::mem::replace(&mut window, c));
+    assert_eq!(window, None);
+    assert!(!cache.contains(&custom));
+    cache.free(&ibeam, |c| ::std::mem::replace(&mut window, c));
+    assert!(!cache.contains(&ibeam));
+}
+
+#[test]
+fn pixel_image_test() {
+    let image = pixel_image((2, 1), &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.pixels.len(), 2);
+    assert!(pixel_image((2, 2), &[1, 2, 3, 4]).is_err());
+    assert!(pixel_image((0, 0), &[]).is_err());
+}
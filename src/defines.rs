@@ -1,4 +1,9 @@
 #![allow(unused)]
+// bumped whenever a message or op changes shape, sent in MSG_OUT_CAPABILITIES
+pub const PROTOCOL_VERSION: u32 = 1;
+// the oldest version a caller may still speak, see --protocol
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
 pub const MSG_OUT_CLOSE: u32 = 0x00;
 pub const MSG_OUT_STATS: u32 = 0x01;
 pub const MSG_OUT_PUTS: u32 = 0x02;
@@ -7,6 +12,8 @@ pub const MSG_OUT_INSPECT: u32 = 0x04;
 pub const MSG_OUT_RESHAPE: u32 = 0x05;
 pub const MSG_OUT_READY: u32 = 0x06;
 pub const MSG_OUT_DRAW_READY: u32 = 0x07;
+pub const MSG_OUT_ERROR: u32 = 0x08;
+pub const MSG_OUT_CAPABILITIES: u32 = 0x09;
 
 pub const MSG_OUT_KEY: u32 = 0x0A;
 pub const MSG_OUT_CODEPOINT: u32 = 0x0B;
@@ -15,8 +22,23 @@ pub const MSG_OUT_MOUSE_BUTTON: u32 = 0x0D;
 pub const MSG_OUT_MOUSE_SCROLL: u32 = 0x0E;
 pub const MSG_OUT_CURSOR_ENTER: u32 = 0x0F;
 pub const MSG_OUT_DROP_PATHS: u32 = 0x10;
+// cursor movement while the cursor is disabled, see CMD_CURSOR_MODE
+pub const MSG_OUT_CURSOR_MOTION: u32 = 0x11;
+// answers CMD_GET_CLIPBOARD with its request id
+pub const MSG_OUT_CLIPBOARD: u32 = 0x12;
+pub const MSG_OUT_FOCUS: u32 = 0x13;
+pub const MSG_OUT_ICONIFY: u32 = 0x14;
+pub const MSG_OUT_MAXIMIZE: u32 = 0x15;
+pub const MSG_OUT_WINDOW_POS: u32 = 0x16;
+// the content scale changed, see update_content_scale for where it comes from
+pub const MSG_OUT_CONTENT_SCALE: u32 = 0x17;
+// answers CMD_QUERY_MONITORS
+pub const MSG_OUT_MONITORS: u32 = 0x18;
+pub const MSG_OUT_MONITOR_EVENT: u32 = 0x19;
+// wraps a message about a window other than the main one
+pub const MSG_OUT_WINDOW: u32 = 0x1A;
 pub const MSG_OUT_CACHE_MISS: u32 = 0x20;
-
+pub const MSG_OUT_CACHE_RESIDENT: u32 = 0x21;
 pub const MSG_OUT_FONT_MISS: u32 = 0x22;
 
 // pub const   MSG_OUT_NEW_DL_ID: u32          = 0x30;
@@ -26,12 +48,20 @@ pub const MSG_OUT_NEW_FONT_ID: u32 = 0x32;
 pub const CMD_RENDER_GRAPH: u32 = 0x01;
 pub const CMD_CLEAR_GRAPH: u32 = 0x02;
 pub const CMD_SET_ROOT: u32 = 0x03;
-// pub const   CMD_CACHE_LOAD: u32             = 0x03;
-// pub const   CMD_CACHE_RELEASE: u32          = 0x04;
+pub const CMD_CACHE_LOAD: u32 = 0x04;
 
 pub const CMD_CLEAR_COLOR: u32 = 0x05;
+pub const CMD_UPDATE_GRAPH: u32 = 0x06;
+pub const CMD_CACHE_RELEASE: u32 = 0x07;
+pub const CMD_CACHE_QUERY: u32 = 0x08;
 
 pub const CMD_INPUT: u32 = 0x0A;
+pub const CMD_CURSOR_MODE: u32 = 0x0B;
+pub const CMD_SET_CLIPBOARD: u32 = 0x0C;
+pub const CMD_GET_CLIPBOARD: u32 = 0x0D;
+pub const CMD_QUERY_MONITORS: u32 = 0x0E;
+// wraps a command for a window other than the main one
+pub const CMD_WINDOW: u32 = 0x0F;
 
 pub const CMD_QUIT: u32 = 0x20;
 pub const CMD_QUERY_STATS: u32 = 0x21;
@@ -43,6 +73,12 @@ pub const CMD_MAXIMIZE: u32 = 0x26;
 pub const CMD_RESTORE: u32 = 0x27;
 pub const CMD_SHOW: u32 = 0x28;
 pub const CMD_HIDE: u32 = 0x29;
+pub const CMD_HEARTBEAT: u32 = 0x2A;
+pub const CMD_FULLSCREEN: u32 = 0x2B;
+pub const CMD_SET_TITLE: u32 = 0x2C;
+pub const CMD_SET_ICON: u32 = 0x2D;
+pub const CMD_SET_CURSOR: u32 = 0x2E;
+pub const CMD_SET_CUSTOM_CURSOR: u32 = 0x2F;
 
 // pub const   CMD_NEW_DL_ID: u32              = 0x30;
 // pub const   CMD_FREE_DL_ID: u32             = 0x31;
@@ -51,10 +87,18 @@ pub const CMD_NEW_TX_ID: u32 = 0x32;
 pub const CMD_FREE_TX_ID: u32 = 0x33;
 pub const CMD_PUT_TX_BLOB: u32 = 0x34;
 pub const CMD_PUT_TX_RAW: u32 = 0x35;
+pub const CMD_NEW_FONT_ID: u32 = 0x36;
 
 pub const CMD_LOAD_FONT_FILE: u32 = 0x37;
 pub const CMD_LOAD_FONT_BLOB: u32 = 0x38;
 pub const CMD_FREE_FONT: u32 = 0x39;
+pub const CMD_FREE_CURSOR: u32 = 0x3A;
+// 0x3B and 0x3C are unused, GLFW 3.2 fixes the other window attributes at
+// creation
+pub const CMD_SET_SIZE_LIMITS: u32 = 0x3D;
+pub const CMD_SET_ASPECT_RATIO: u32 = 0x3E;
+pub const CMD_NEW_WINDOW: u32 = 0x3F;
+pub const CMD_CLOSE_WINDOW: u32 = 0x40;
 
 // here to test recovery
 pub const CMD_CRASH: u32 = 0xFE;
@@ -67,6 +111,24 @@ pub const MSG_MOUSE_SCROLL_MASK: u32 = 0x0010;
 pub const MSG_MOUSE_ENTER_MASK: u32 = 0x0020;
 pub const MSG_DROP_PATHS_MASK: u32 = 0x0040;
 pub const MSG_RESHAPE_MASK: u32 = 0x0080;
+pub const MSG_FOCUS_MASK: u32 = 0x0100;
+pub const MSG_ICONIFY_MASK: u32 = 0x0200;
+pub const MSG_MAXIMIZE_MASK: u32 = 0x0400;
+pub const MSG_WINDOW_POS_MASK: u32 = 0x0800;
+pub const MSG_CONTENT_SCALE_MASK: u32 = 0x1000;
+
+pub const TRANSPORT_STDIO: u32 = 0x01;
+pub const TRANSPORT_UNIX: u32 = 0x02;
+pub const TRANSPORT_TCP: u32 = 0x04;
+pub const TRANSPORT_REPLAY: u32 = 0x08;
+
+// optional features in MSG_OUT_CAPABILITIES. a clear bit means the driver
+// was built without it
+pub const FEATURE_RAW_MOUSE_MOTION: u32 = 0x01;
+
+// resource kinds for CMD_CACHE_LOAD / CMD_CACHE_RELEASE
+pub const RESOURCE_TEXTURE: u32 = 0x01;
+pub const RESOURCE_FONT: u32 = 0x02;
 
 // state control
 pub const OP_PUSH_STATE: u32 = 0x01;
@@ -82,6 +144,8 @@ pub const OP_PAINT_RADIAL: u32 = 0x08;
 pub const OP_PAINT_IMAGE: u32 = 0x09;
 
 pub const OP_ANTI_ALIAS: u32 = 0x0A;
+// OP_PAINT_IMAGE with a handle from MSG_OUT_NEW_TX_ID instead of the key
+pub const OP_PAINT_IMAGE_ID: u32 = 0x0B;
 
 pub const OP_STROKE_WIDTH: u32 = 0x0C;
 pub const OP_STROKE_COLOR: u32 = 0x0D;
@@ -140,6 +204,8 @@ pub const OP_FONT_BLUR: u32 = 0x41;
 pub const OP_FONT_SIZE: u32 = 0x42;
 pub const OP_TEXT_ALIGN: u32 = 0x43;
 pub const OP_TEXT_HEIGHT: u32 = 0x44;
+// OP_FONT with a handle from MSG_OUT_NEW_FONT_ID instead of the name
+pub const OP_FONT_ID: u32 = 0x45;
 
 pub const OP_TERMINATE: u32 = 0xFF;
 
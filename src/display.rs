@@ -0,0 +1,417 @@
+use crate::event::*;
+use glfw::{Glfw, PixelImage, Window, WindowHint, WindowMode};
+use crate::monitor::window_monitor_scale;
+use nanovg::Image;
+use nanovg_sys::nvglImageHandleGL2;
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScreenMode {
+    Windowed,
+    // takes over the monitor, possibly switching its video mode
+    Exclusive,
+    // covers the monitor at its current video mode
+    Borderless,
+}
+
+impl ScreenMode {
+    pub fn from_u32(mode: u32) -> Option<ScreenMode> {
+        match mode {
+            0 => Some(ScreenMode::Windowed),
+            1 => Some(ScreenMode::Exclusive),
+            2 => Some(ScreenMode::Borderless),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayMode {
+    pub mode: ScreenMode,
+    // index into the connected monitors, 0 is the primary one
+    pub monitor: usize,
+    // video mode for exclusive fullscreen, the monitor's current one if unset
+    pub size: Option<(u32, u32)>,
+    pub refresh_rate: Option<u32>,
+}
+
+// "windowed", "exclusive" or "borderless", optionally followed by
+// ":<monitor>" and, for exclusive, ":<width>x<height>[@<refresh rate>]"
+pub fn parse_display_mode(spec: &str) -> Result<DisplayMode, String> {
+    let mut parts = spec.split(':');
+    let mode = match parts.next() {
+        Some("windowed") => ScreenMode::Windowed,
+        Some("exclusive") => ScreenMode::Exclusive,
+        Some("borderless") => ScreenMode::Borderless,
+        _ => return Err(format!("unknown fullscreen mode: {}", spec)),
+    };
+    let monitor = match parts.next() {
+        Some(monitor) => monitor
+            .parse::<usize>()
+            .map_err(|_| format!("invalid monitor: {}", monitor))?,
+        None => 0,
+    };
+    let (size, refresh_rate) = match parts.next() {
+        Some(video_mode) => {
+            let (video_mode, refresh_rate) = match video_mode.find('@') {
+                Some(at) => {
+                    let rate = &video_mode[at + 1..];
+                    let rate = rate
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid refresh rate: {}", rate))?;
+                    (&video_mode[..at], Some(rate))
+                }
+                None => (video_mode, None),
+            };
+            let size = parse_pair(video_mode, 'x')
+                .ok_or_else(|| format!("invalid video mode: {}", video_mode))?;
+            (Some(size), refresh_rate)
+        }
+        None => (None, None),
+    };
+    if parts.next().is_some() {
+        return Err(format!("invalid fullscreen spec: {}", spec));
+    }
+    Ok(DisplayMode {
+        mode: mode,
+        monitor: monitor,
+        size: size,
+        refresh_rate: refresh_rate,
+    })
+}
+
+// "<a><sep><b>", as in 1920x1080 or 16:9
+fn parse_pair(spec: &str, sep: char) -> Option<(u32, u32)> {
+    let mut pair = spec.splitn(2, sep).map(|v| v.parse::<u32>());
+    match (pair.next(), pair.next()) {
+        (Some(Ok(a)), Some(Ok(b))) => Some((a, b)),
+        _ => None,
+    }
+}
+
+pub fn set_display_mode(
+    glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    mode: &DisplayMode,
+) -> Result<(), String> {
+    let window = &mut window_data.window;
+    let is_windowed = window.with_window_mode(|current| match current {
+        WindowMode::Windowed => true,
+        WindowMode::FullScreen(_) => false,
+    });
+    match mode.mode {
+        ScreenMode::Windowed => {
+            if !is_windowed {
+                let (x, y, width, height) = window_data.windowed_rect;
+                window.set_monitor(
+                    WindowMode::Windowed,
+                    x,
+                    y,
+                    width as u32,
+                    height as u32,
+                    None,
+                );
+            }
+        }
+        ScreenMode::Exclusive | ScreenMode::Borderless => {
+            // remembered so going back to windowed restores the old place
+            if is_windowed {
+                let (x, y) = window.get_pos();
+                let (width, height) = window.get_size();
+                window_data.windowed_rect = (x, y, width, height);
+            }
+            glfw.with_connected_monitors(|_, monitors| -> Result<(), String> {
+                let monitor = monitors
+                    .get(mode.monitor)
+                    .ok_or_else(|| format!("no monitor {}", mode.monitor))?;
+                let current = monitor
+                    .get_video_mode()
+                    .ok_or_else(|| format!("no video mode for monitor {}", mode.monitor))?;
+                let ((width, height), refresh_rate) = match mode.mode {
+                    ScreenMode::Exclusive => (
+                        mode.size.unwrap_or((current.width, current.height)),
+                        mode.refresh_rate.unwrap_or(current.refresh_rate),
+                    ),
+                    _ => ((current.width, current.height), current.refresh_rate),
+                };
+                window.set_monitor(
+                    WindowMode::FullScreen(monitor),
+                    0,
+                    0,
+                    width,
+                    height,
+                    Some(refresh_rate),
+                );
+                Ok(())
+            })?;
+        }
+    }
+    send_window_reshape(glfw, window_data);
+    Ok(())
+}
+
+// moves a windowed window to the middle of a monitor. GLFW 3.2 cannot tell
+// where task bars and docks are, so this is the middle of its current mode
+pub fn center_on_monitor(
+    glfw: &mut Glfw,
+    window_data: &mut WindowData,
+    monitor: usize,
+) -> Result<(), String> {
+    let (x, y, width, height) =
+        glfw.with_connected_monitors(|_, monitors| -> Result<_, String> {
+            let monitor = monitors
+                .get(monitor)
+                .ok_or_else(|| format!("no monitor {}", monitor))?;
+            let (x, y) = monitor.get_pos();
+            let mode = monitor
+                .get_video_mode()
+                .ok_or_else(|| "monitor has no video mode".to_string())?;
+            Ok((x, y, mode.width as i32, mode.height as i32))
+        })?;
+    let (window_width, window_height) = window_data.window.get_size();
+    let x = x + (width - window_width).max(0) / 2;
+    let y = y + (height - window_height).max(0) / 2;
+    window_data.window.set_pos(x, y);
+    window_data.windowed_rect = (x, y, window_width, window_height);
+    Ok(())
+}
+
+// GLFW 3.2 only reads floating and decorated when a window is created, and
+// has no opacity or transparent framebuffer at all. these are the launch
+// options, which every new window is created with
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowAttribs {
+    // always on top of other windows
+    pub floating: bool,
+    pub decorated: bool,
+    pub min_size: Option<(u32, u32)>,
+    pub max_size: Option<(u32, u32)>,
+    pub aspect_ratio: Option<(u32, u32)>,
+}
+
+impl Default for WindowAttribs {
+    fn default() -> WindowAttribs {
+        WindowAttribs {
+            floating: false,
+            decorated: true,
+            min_size: None,
+            max_size: None,
+            aspect_ratio: None,
+        }
+    }
+}
+
+impl WindowAttribs {
+    // the ones glfw reads when creating the window
+    pub fn hints(&self) -> Vec<WindowHint> {
+        vec![
+            WindowHint::Floating(self.floating),
+            WindowHint::Decorated(self.decorated),
+        ]
+    }
+}
+
+// comma separated, any of "floating", "undecorated", "min=<w>x<h>",
+// "max=<w>x<h>" and "aspect=<w>:<h>"
+pub fn parse_window_attribs(spec: &str) -> Result<WindowAttribs, String> {
+    let mut attribs = WindowAttribs::default();
+    for part in spec.split(',').filter(|part| !part.is_empty()) {
+        let (name, value) = match part.find('=') {
+            Some(eq) => (&part[..eq], Some(&part[eq + 1..])),
+            None => (part, None),
+        };
+        let invalid = || format!("invalid window attribute: {}", part);
+        match (name, value) {
+            ("opacity", _) | ("transparent", _) => {
+                return Err(format!("{} is not supported by GLFW 3.2", name))
+            }
+            ("floating", None) => attribs.floating = true,
+            ("undecorated", None) => attribs.decorated = false,
+            ("min", Some(value)) => {
+                attribs.min_size = Some(parse_pair(value, 'x').ok_or_else(invalid)?)
+            }
+            ("max", Some(value)) => {
+                attribs.max_size = Some(parse_pair(value, 'x').ok_or_else(invalid)?)
+            }
+            ("aspect", Some(value)) => {
+                attribs.aspect_ratio = Some(
+                    parse_pair(value, ':')
+                        .filter(|(numer, denom)| *numer > 0 && *denom > 0)
+                        .ok_or_else(invalid)?,
+                )
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(attribs)
+}
+
+// glfw-rs has no getters for these, but GLFW 3.2 can read them back
+pub fn is_floating(window: &Window) -> bool {
+    unsafe { ::glfw::ffi::glfwGetWindowAttrib(window.window_ptr(), ::glfw::ffi::FLOATING) != 0 }
+}
+
+pub fn is_decorated(window: &Window) -> bool {
+    unsafe { ::glfw::ffi::glfwGetWindowAttrib(window.window_ptr(), ::glfw::ffi::DECORATED) != 0 }
+}
+
+// the attributes that can change after the window exists, the hints were
+// used when it was created
+pub fn set_window_attribs(window_data: &mut WindowData, attribs: WindowAttribs) {
+    set_size_limits(window_data, attribs.min_size, attribs.max_size);
+    set_aspect_ratio(window_data, attribs.aspect_ratio);
+    window_data.attribs = attribs;
+}
+
+pub fn set_size_limits(
+    window_data: &mut WindowData,
+    min_size: Option<(u32, u32)>,
+    max_size: Option<(u32, u32)>,
+) {
+    window_data.window.set_size_limits(
+        min_size.map(|size| size.0),
+        min_size.map(|size| size.1),
+        max_size.map(|size| size.0),
+        max_size.map(|size| size.1),
+    );
+    window_data.attribs.min_size = min_size;
+    window_data.attribs.max_size = max_size;
+}
+
+pub fn set_aspect_ratio(window_data: &mut WindowData, ratio: Option<(u32, u32)>) {
+    let (numer, denom) =
+        ratio.unwrap_or((::glfw::ffi::DONT_CARE as u32, ::glfw::ffi::DONT_CARE as u32));
+    window_data.window.set_aspect_ratio(numer, denom);
+    window_data.attribs.aspect_ratio = ratio;
+}
+
+// reports the current sizes even if nothing changed, so every window
+// command gets an answer
+pub fn send_window_reshape(glfw: &mut Glfw, window_data: &mut WindowData) {
+    let (width, height) = window_data.window.get_framebuffer_size();
+    reshape_framebuffer(window_data, width, height);
+    let (width, height) = window_data.window.get_size();
+    reshape_window(window_data, width, height);
+    update_content_scale(glfw, window_data);
+    send_pending_reshape(window_data);
+}
+
+// GLFW 3.2 cannot be asked for the content scale. where the framebuffer is
+// denser than the window, as on a Retina display, that ratio is the scale,
+// otherwise it comes from the monitor the window is on
+pub fn update_content_scale(glfw: &mut Glfw, window_data: &mut WindowData) {
+    window_data.rescale_pending = false;
+    let (xratio, yratio) = window_data.get_ratio();
+    let (xscale, yscale) = if xratio > 1.0 || yratio > 1.0 {
+        (xratio, yratio)
+    } else {
+        let scale = window_monitor_scale(glfw, &window_data.window);
+        (scale, scale)
+    };
+    if (xscale, yscale) != window_data.content_scale {
+        rescale_window(window_data, xscale, yscale);
+    }
+}
+
+// the icon is built from textures that are already uploaded, several sizes
+// let the platform pick the best one
+pub fn set_window_icon(
+    window_data: &mut WindowData,
+    ctx: &Context,
+    keys: &[String],
+) -> Result<(), String> {
+    let mut images = vec![];
+    for key in keys {
+        let image = ctx
+            .cache
+            .textures
+            .get(key)
+            .ok_or_else(|| format!("no texture: {}", key))?;
+        images.push(read_pixels(ctx.ctx, image));
+    }
+    window_data.window.set_icon_from_pixels(images);
+    Ok(())
+}
+
+// nanovg keeps no copy of the decoded pixels, read them back from GL
+pub fn read_pixels(ctx: &NanoContext, image: &Image) -> PixelImage {
+    let (width, height) = image.size();
+    let mut pixels = vec![0u32; width * height];
+    unsafe {
+        let texture = nvglImageHandleGL2(ctx.raw(), image.raw());
+        ::gl::BindTexture(::gl::TEXTURE_2D, texture);
+        ::gl::GetTexImage(
+            ::gl::TEXTURE_2D,
+            0,
+            ::gl::RGBA,
+            ::gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+        ::gl::BindTexture(::gl::TEXTURE_2D, 0);
+    }
+    PixelImage {
+        width: width as u32,
+        height: height as u32,
+        pixels: pixels,
+    }
+}
+
+#[test]
+fn parse_display_mode_test() {
+    assert_eq!(
+        parse_display_mode("borderless").unwrap(),
+        DisplayMode {
+            mode: ScreenMode::Borderless,
+            monitor: 0,
+            size: None,
+            refresh_rate: None,
+        }
+    );
+    assert_eq!(
+        parse_display_mode("exclusive:1:1920x1080@60").unwrap(),
+        DisplayMode {
+            mode: ScreenMode::Exclusive,
+            monitor: 1,
+            size: Some((1920, 1080)),
+            refresh_rate: Some(60),
+        }
+    );
+    assert_eq!(
+        parse_display_mode("exclusive:0:800x600").unwrap().size,
+        Some((800, 600))
+    );
+    assert_eq!(
+        parse_display_mode("windowed").unwrap().mode,
+        ScreenMode::Windowed
+    );
+    assert!(parse_display_mode("fullscreen").is_err());
+    assert!(parse_display_mode("exclusive:x").is_err());
+    assert!(parse_display_mode("exclusive:0:1920").is_err());
+    assert!(parse_display_mode("exclusive:0:1920x1080@").is_err());
+    assert!(parse_display_mode("exclusive:0:1920x1080:1").is_err());
+}
+
+#[test]
+fn parse_window_attribs_test() {
+    assert_eq!(parse_window_attribs("").unwrap(), WindowAttribs::default());
+    assert_eq!(
+        parse_window_attribs("floating,undecorated,min=320x240,max=1920x1080,aspect=16:9")
+            .unwrap(),
+        WindowAttribs {
+            floating: true,
+            decorated: false,
+            min_size: Some((320, 240)),
+            max_size: Some((1920, 1080)),
+            aspect_ratio: Some((16, 9)),
+        }
+    );
+    assert_eq!(
+        parse_window_attribs("opacity=0.5").unwrap_err(),
+        "opacity is not supported by GLFW 3.2"
+    );
+    assert!(parse_window_attribs("transparent").is_err());
+    assert!(parse_window_attribs("floating=1").is_err());
+    assert!(parse_window_attribs("min=320").is_err());
+    assert!(parse_window_attribs("aspect=16:0").is_err());
+    assert!(parse_window_attribs("borderless").is_err());
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub cmd: u32,
+    pub script_id: Option<u32>,
+    // byte offset for decode errors, op index for errors found while rendering
+    pub offset: u64,
+    pub reason: String,
+}
+
+impl ProtocolError {
+    pub fn new(cmd: u32, offset: u64, reason: impl fmt::Display) -> ProtocolError {
+        ProtocolError {
+            cmd: cmd,
+            script_id: None,
+            offset: offset,
+            reason: reason.to_string(),
+        }
+    }
+    pub fn in_script(mut self, script_id: u32) -> ProtocolError {
+        self.script_id = Some(script_id);
+        self
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.script_id {
+            Some(id) => write!(
+                f,
+                "script {} op {:#X} at offset {}: {}",
+                id, self.cmd, self.offset, self.reason
+            ),
+            None => write!(
+                f,
+                "command {:#X} at offset {}: {}",
+                self.cmd, self.offset, self.reason
+            ),
+        }
+    }
+}
+
+impl ::std::error::Error for ProtocolError {}
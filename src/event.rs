@@ -1,11 +1,15 @@
 use crate::comms::*;
 use crate::defines::*;
-use glfw::WindowEvent;
+use glfw::{CursorMode, WindowEvent};
+use std::path::PathBuf;
 use crate::types::*;
 pub fn handle_window_event(window_data: &mut WindowData, event: WindowEvent) {
     match event {
         WindowEvent::FramebufferSize(w, h) => reshape_framebuffer(window_data, w, h),
-        WindowEvent::Size(w, h) => reshape_window(window_data, w, h),
+        WindowEvent::Size(w, h) => {
+            reshape_window(window_data, w, h);
+            check_maximized(window_data);
+        }
         WindowEvent::Key(key, scancode, action, mods) => {
             key_callback(
                 window_data,
@@ -26,7 +30,11 @@ pub fn handle_window_event(window_data: &mut WindowData, event: WindowEvent) {
             scroll_callback(window_data, xoffset, yoffset);
         }
         WindowEvent::CursorEnter(entered) => cursor_enter_callback(window_data, entered as i32),
+        WindowEvent::FileDrop(paths) => drop_callback(window_data, paths),
         WindowEvent::Close => window_close_callback(window_data),
+        WindowEvent::Focus(focused) => focus_callback(window_data, focused as i32),
+        WindowEvent::Iconify(iconified) => iconify_callback(window_data, iconified as i32),
+        WindowEvent::Pos(x, y) => window_pos_callback(window_data, x, y),
         _ => (),
     };
 }
@@ -42,7 +50,13 @@ pub fn charmods_callback(window_data: &mut WindowData, codepoint: u32, mods: i32
 }
 pub fn cursor_pos_callback(window_data: &mut WindowData, xpos: f64, ypos: f64) {
     let pos = (xpos as f32, ypos as f32);
-    if window_data.last_cursor_pos != pos
+    if window_data.cursor_mode == CursorMode::Disabled {
+        let (x, y) = window_data.last_cursor_pos;
+        if window_data.input_flags & MSG_MOUSE_MOVE_MASK != 0 && (x, y) != pos {
+            send_cursor_motion(pos.0 - x, pos.1 - y);
+        }
+        window_data.last_cursor_pos = pos;
+    } else if window_data.last_cursor_pos != pos
     //&& window_data.input_flags & MSG_MOUSE_MOVE_MASK != 0
     {
         send_cursor_pos(pos.0, pos.1);
@@ -51,32 +65,91 @@ pub fn cursor_pos_callback(window_data: &mut WindowData, xpos: f64, ypos: f64) {
 }
 pub fn mouse_button_callback(window_data: &mut WindowData, button: i32, action: i32, mods: i32) {
     if window_data.input_flags & MSG_MOUSE_BUTTON_MASK != 0 {
-        let (x, y) = window_data.window.get_cursor_pos();
-        send_mouse_button(button, action, mods, x as f32, y as f32);
+        let (x, y) = window_data.get_cursor_pos();
+        send_mouse_button(button, action, mods, x, y);
     }
 }
 pub fn scroll_callback(window_data: &mut WindowData, xoffset: f64, yoffset: f64) {
     if window_data.input_flags & MSG_MOUSE_SCROLL_MASK != 0 {
-        let (x, y) = window_data.window.get_cursor_pos();
-        send_scroll(xoffset as f32, yoffset as f32, x as f32, y as f32);
+        let (x, y) = window_data.get_cursor_pos();
+        send_scroll(xoffset as f32, yoffset as f32, x, y);
     }
 }
 pub fn cursor_enter_callback(window_data: &mut WindowData, entered: i32) {
     if window_data.input_flags & MSG_MOUSE_ENTER_MASK != 0 {
-        let (x, y) = window_data.window.get_cursor_pos();
-        send_cursor_enter(entered, x as f32, y as f32);
+        let (x, y) = window_data.get_cursor_pos();
+        send_cursor_enter(entered, x, y);
+    }
+}
+pub fn drop_callback(window_data: &mut WindowData, paths: Vec<PathBuf>) {
+    if window_data.input_flags & MSG_DROP_PATHS_MASK != 0 {
+        let (x, y) = window_data.get_cursor_pos();
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        send_drop_paths(&paths, x, y);
     }
 }
 pub fn window_close_callback(window_data: &mut WindowData) {
     send_close();
     window_data.window.set_should_close(false);
 }
+pub fn focus_callback(window_data: &mut WindowData, focused: i32) {
+    if window_data.input_flags & MSG_FOCUS_MASK != 0 {
+        send_focus(focused);
+    }
+}
+pub fn iconify_callback(window_data: &mut WindowData, iconified: i32) {
+    if window_data.input_flags & MSG_ICONIFY_MASK != 0 {
+        send_iconify(iconified);
+    }
+}
+// GLFW 3.2 has no maximize event, but maximizing or restoring the window
+// always resizes it
+pub fn check_maximized(window_data: &mut WindowData) {
+    let maximized = window_data.window.is_maximized();
+    if maximized != window_data.maximized {
+        window_data.maximized = maximized;
+        if window_data.input_flags & MSG_MAXIMIZE_MASK != 0 {
+            send_maximize(maximized as i32);
+        }
+    }
+}
+pub fn window_pos_callback(window_data: &mut WindowData, x: i32, y: i32) {
+    window_data.rescale_pending = true;
+    if window_data.input_flags & MSG_WINDOW_POS_MASK != 0 {
+        send_window_pos(x, y);
+    }
+}
 pub fn reshape_framebuffer(window_data: &mut WindowData, width: i32, height: i32) {
     window_data.frame_size = (width, height);
+    window_data.rescale_pending = true;
+    window_data.reshape_pending = true;
     window_data.redraw = true;
 }
 pub fn reshape_window(window_data: &mut WindowData, width: i32, height: i32) {
     window_data.window_size = (width, height);
-    send_reshape(width, height, width, height);
+    window_data.rescale_pending = true;
+    window_data.reshape_pending = true;
     window_data.redraw = true;
 }
+pub fn rescale_window(window_data: &mut WindowData, xscale: f32, yscale: f32) {
+    window_data.content_scale = (xscale, yscale);
+    window_data.reshape_pending = true;
+    window_data.redraw = true;
+    if window_data.input_flags & MSG_CONTENT_SCALE_MASK != 0 {
+        send_content_scale(xscale, yscale);
+    }
+}
+// a resize reports the window and the framebuffer separately, both go out
+// in one message once the events of a frame are handled
+pub fn send_pending_reshape(window_data: &mut WindowData) {
+    if window_data.reshape_pending {
+        let (width, height) = window_data.window_size;
+        let (frame_width, frame_height) = window_data.frame_size;
+        let (xscale, yscale) = window_data.content_scale;
+        send_reshape(width, height, frame_width, frame_height, xscale, yscale);
+        window_data.reshape_pending = false;
+    }
+}
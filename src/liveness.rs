@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// exit codes for the different ways of losing the Elixir side, so a
+// supervisor can tell them apart from a crash (1) or a normal quit (0)
+pub const EXIT_CALLER_CLOSED: i32 = 3;
+pub const EXIT_PARENT_CHANGED: i32 = 4;
+pub const EXIT_HEARTBEAT_TIMEOUT: i32 = 5;
+
+static OUTPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+pub fn mark_output_closed() {
+    OUTPUT_CLOSED.store(true, Ordering::SeqCst);
+}
+
+fn is_output_closed() -> bool {
+    OUTPUT_CLOSED.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallerGone {
+    InputClosed,
+    OutputClosed,
+    ParentChanged,
+    HeartbeatTimeout,
+}
+
+impl CallerGone {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CallerGone::InputClosed | CallerGone::OutputClosed => EXIT_CALLER_CLOSED,
+            CallerGone::ParentChanged => EXIT_PARENT_CHANGED,
+            CallerGone::HeartbeatTimeout => EXIT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+pub struct Liveness {
+    parent_pid: Option<u32>,
+    heartbeat: Option<Duration>,
+    last_seen: Instant,
+    input_closed: bool,
+}
+
+impl Liveness {
+    pub fn new() -> Liveness {
+        Liveness {
+            parent_pid: None,
+            heartbeat: None,
+            last_seen: Instant::now(),
+            input_closed: false,
+        }
+    }
+
+    // only meaningful when the BEAM spawned us, a driver started by hand and
+    // connected over a socket has an unrelated parent
+    pub fn watch_parent(&mut self) {
+        self.parent_pid = current_parent_pid();
+    }
+
+    pub fn set_heartbeat(&mut self, timeout: Option<Duration>) {
+        self.heartbeat = timeout;
+        self.last_seen = Instant::now();
+    }
+
+    pub fn saw_message(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    pub fn input_closed(&mut self) {
+        self.input_closed = true;
+    }
+
+    pub fn check(&self, now: Instant) -> Option<CallerGone> {
+        if self.input_closed {
+            return Some(CallerGone::InputClosed);
+        }
+        if is_output_closed() {
+            return Some(CallerGone::OutputClosed);
+        }
+        if self.parent_pid.is_some() && self.parent_pid != current_parent_pid() {
+            return Some(CallerGone::ParentChanged);
+        }
+        match self.heartbeat {
+            Some(timeout) if now.duration_since(self.last_seen) > timeout => {
+                Some(CallerGone::HeartbeatTimeout)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn current_parent_pid() -> Option<u32> {
+    Some(::std::os::unix::process::parent_id())
+}
+
+#[cfg(not(unix))]
+fn current_parent_pid() -> Option<u32> {
+    None
+}
+
+#[test]
+fn liveness_heartbeat_test() {
+    let mut liveness = Liveness::new();
+    let now = Instant::now();
+    assert_eq!(liveness.check(now + Duration::from_secs(60)), None);
+
+    liveness.set_heartbeat(Some(Duration::from_millis(500)));
+    let now = Instant::now();
+    assert_eq!(liveness.check(now), None);
+    assert_eq!(
+        liveness.check(now + Duration::from_secs(1)),
+        Some(CallerGone::HeartbeatTimeout)
+    );
+    liveness.set_heartbeat(None);
+    assert_eq!(liveness.check(now + Duration::from_secs(1)), None);
+}
+
+#[test]
+fn liveness_input_closed_test() {
+    let mut liveness = Liveness::new();
+    liveness.watch_parent();
+    assert_eq!(liveness.check(Instant::now()), None);
+    liveness.input_closed();
+    let gone = liveness.check(Instant::now());
+    assert_eq!(gone, Some(CallerGone::InputClosed));
+    assert_eq!(gone.unwrap().exit_code(), EXIT_CALLER_CLOSED);
+}
@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// how much the driver itself prints to stderr. stdout may be the port, so
+// diagnostics never go there
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(name: &str) -> Result<LogLevel, String> {
+        match name {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("unknown log level: {}", name)),
+        }
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Warn as usize);
+
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: LogLevel, msg: impl AsRef<str>) {
+    if log_enabled(level) {
+        eprintln!("scenic_driver_glfw: {}", msg.as_ref());
+    }
+}
@@ -1,39 +1,90 @@
 #[macro_use]
 mod util;
+mod cache;
+mod capabilities;
+mod capture;
+mod codec;
 mod comms;
+mod config;
+mod cursor;
 mod defines;
+mod display;
+mod error;
 mod event;
+mod liveness;
+mod log;
+mod monitor;
+mod ops;
+mod outbound;
 mod script;
+mod transport;
 mod types;
+mod windows;
 
+use crate::capture::*;
 use crate::comms::*;
+use crate::config::*;
+use crate::display::*;
 use crate::event::*;
-use glfw::{Context, Glfw, WindowHint, WindowMode};
+use crate::log::*;
+use crate::monitor::watch_monitors;
+use glfw::{Context, Glfw, SwapInterval, WindowHint, WindowMode};
 use crate::script::*;
+use std::io::BufReader;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
+use crate::transport::*;
 use crate::types::*;
 use crate::util::*;
+use crate::windows::*;
 
 fn main() {
-    let args: Vec<String> = ::std::env::args().collect();
-    if args.len() != 6 {
-        print!(
-            "\r\nscenic_driver_glfw should be launched via the Scenic.Driver.Glfw library.\r\n\r\n"
-        );
-        return;
-    }
-    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
+    let args: Vec<String> = ::std::env::args().skip(1).collect();
+    let options = match load_options(&args, &|name| ::std::env::var(name).ok()) {
+        Ok(Launch::Run(options)) => options,
+        Ok(Launch::Help) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Ok(Launch::NoArgs) => {
+            print!(
+                "\r\nscenic_driver_glfw should be launched via the Scenic.Driver.Glfw library.\r\n\r\n"
+            );
+            return;
+        }
+        Err(err) => exit_with(err),
+    };
+    set_log_level(options.log_level);
+
+    let transport = parse_transport(&options.transport).unwrap_or_else(|err| exit_with(err));
+    log(
+        LogLevel::Info,
+        format!("waiting on {}", transport.describe()),
+    );
+    let (reader, writer) = transport
+        .open()
+        .unwrap_or_else(|err| exit_with(format!("cannot open {}: {}", transport.describe(), err)));
+    let reader: Reader = Box::new(BufReader::with_capacity(options.block_size, reader));
+    set_output(writer);
+
+    let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS)
+        .unwrap_or_else(|err| exit_with(format!("cannot start glfw: {:?}", err)));
+    watch_monitors(&mut glfw);
 
-    let width = args[1].parse::<i32>().unwrap();
-    let height = args[2].parse::<i32>().unwrap();
-    // let block_size = args[5].parse::<i32>().unwrap();
-    set_window_hints(&mut glfw, &args[4]);
+    let (width, height) = (options.width, options.height);
+    set_window_hints(&mut glfw, &options);
     let (mut window, events) =
-        glfw.create_window(width as u32, height as u32, &args[3], WindowMode::Windowed)
+        glfw.create_window(width as u32, height as u32, &options.title, WindowMode::Windowed)
             .expect_or_send("cannot create window");
 
     glfw.make_context_current(Some(&window));
+    if let Some(vsync) = options.vsync {
+        glfw.set_swap_interval(if vsync {
+            SwapInterval::Sync(1)
+        } else {
+            SwapInterval::None
+        });
+    }
     let ctx = ::nanovg::ContextBuilder::new()
         .antialias()
         .stencil_strokes()
@@ -45,72 +96,168 @@ fn main() {
 
     let mut context = types::Context {
         ctx: &ctx,
-        textures: Default::default(),
+        cache: cache::ResourceCache::new(),
     };
 
-    let mut window_data = setup_window_data(window, width, height);
+    let mut window_data = setup_window_data(&mut glfw, window, events, width, height);
+    window_data.script_stack.max_depth = options.max_script_depth;
+    set_window_attribs(&mut window_data, options.window.clone());
+    if transport.watch_parent() {
+        window_data.liveness.watch_parent();
+    }
+    // monitor, fullscreen and video mode at launch. centring first means
+    // leaving fullscreen later lands on the same monitor
+    if let Some(monitor) = options.monitor {
+        center_on_monitor(&mut glfw, &mut window_data, monitor)
+            .unwrap_or_else(|err| exit_with(format!("--monitor: {}", err)));
+    }
+    if let Some(mode) = &options.fullscreen {
+        set_display_mode(&mut glfw, &mut window_data, mode)
+            .unwrap_or_else(|err| exit_with(format!("--fullscreen: {}", err)));
+    }
+    let mut windows = Windows::new(
+        window_data,
+        shared_window_hints(&options),
+        options.window.clone(),
+    );
+    send_capabilities(max_texture_size());
     send_ready(0);
+    flush_output();
 
     let (std_channel_send, mut std_channel_recv) = ::std::sync::mpsc::channel::<Message>();
-    start_read_stdin_thread(std_channel_send);
-    let draw_interval = Duration::from_micros(1000_000 / 60);
-    let mut last_draw = Instant::now();
-    while window_data.keep_going && !is_caller_down() {
-        if (handle_stdio_in(
-            &mut window_data,
+    let capture = options.capture.as_ref().and_then(|path| open_capture(path));
+    start_read_thread(reader, capture, std_channel_send);
+    let draw_interval = Duration::from_micros(1000_000 / options.frame_rate as u64);
+    let mut last_frame = Instant::now();
+    let mut caller_gone = None;
+    while windows.keep_going() {
+        caller_gone = windows.main().liveness.check(Instant::now());
+        if caller_gone.is_some() {
+            break;
+        }
+        handle_stdio_in(
+            &mut windows,
             &mut glfw,
             &mut context,
             &mut std_channel_recv,
-        ) || window_data.redraw) && last_draw.elapsed() > draw_interval
-        {
-            window_data.redraw = false;
-            unsafe {
-                ::gl::Clear(::gl::COLOR_BUFFER_BIT);
+        );
+        flush_replies();
+        // once per frame the windows that changed are drawn and, after this
+        // round of events, everything queued since the last frame goes out
+        let frame_due = last_frame.elapsed() > draw_interval;
+        if frame_due {
+            last_frame = Instant::now();
+            for (id, window_data) in windows.iter_mut() {
+                if window_data.redraw {
+                    with_window(id, || draw_window(window_data, &context));
+                }
             }
-            let root_script = window_data.root_script;
-            ctx.frame(
-                window_data.get_window_size_float(),
-                window_data.get_ratio().0,
-                |mut frame| {
-                    if root_script > 0 {
-                        run_scripts(
-                            &mut window_data,
-                            root_script as u32,
-                            &mut context,
-                            &mut frame,
-                        );
-                    }
-                },
-            );
-            window_data.window.swap_buffers();
-            last_draw = Instant::now();
         }
         glfw.poll_events();
         let mut has_event = false;
-        for (_, event) in glfw::flush_messages(&events) {
-            handle_window_event(&mut window_data, event);
-            has_event = true;
+        for (id, window_data) in windows.iter_mut() {
+            let events: Vec<_> = glfw::flush_messages(&window_data.events).collect();
+            has_event = has_event || !events.is_empty();
+            with_window(id, || {
+                for (_, event) in events {
+                    handle_window_event(window_data, event);
+                }
+                if window_data.rescale_pending {
+                    update_content_scale(&mut glfw, window_data);
+                }
+                send_pending_reshape(window_data);
+            });
+        }
+        if frame_due {
+            flush_output();
+        } else {
+            flush_replies();
         }
         if !has_event {
             sleep(Duration::from_millis(1));
         }
     }
-    cleanup_window(window_data);
+    // textures and fonts live in the nanovg context, which needs the GL
+    // context of the main window, so tear down in that order
+    windows.main().window.make_current();
+    drop(context);
+    drop(ctx);
+    cleanup_windows(windows);
     ::glfw::terminate();
+    transport.close();
+    if let Some(gone) = caller_gone {
+        log(LogLevel::Warn, format!("caller gone ({:?}), exiting", gone));
+        ::std::process::exit(gone.exit_code());
+    }
+    close_output();
+}
+
+// bad options, or a transport that cannot open, end the driver with a
+// message whatever the log level
+fn exit_with(err: impl AsRef<str>) -> ! {
+    eprintln!("scenic_driver_glfw: {}", err.as_ref());
+    ::std::process::exit(1);
 }
 
-fn set_window_hints(glfw: &mut Glfw, resizable: &String) {
-    if resizable != "true" {
+fn set_window_hints(glfw: &mut Glfw, options: &Options) {
+    if !options.resizable {
         glfw.window_hint(WindowHint::Resizable(false));
     }
+    for hint in shared_window_hints(options) {
+        glfw.window_hint(hint);
+    }
+    // shown by CMD_SHOW
+    if options.hidden {
+        glfw.window_hint(WindowHint::Visible(false));
+    }
     glfw.window_hint(WindowHint::Focused(true));
-    glfw.window_hint(WindowHint::ContextVersionMajor(2));
-    glfw.window_hint(WindowHint::ContextVersionMinor(0));
+}
+
+// the hints windows opened with CMD_NEW_WINDOW are made with too
+fn shared_window_hints(options: &Options) -> Vec<WindowHint> {
+    let mut hints = options.window.hints();
+    if options.msaa > 0 {
+        hints.push(WindowHint::Samples(Some(options.msaa)));
+    }
+    // nanovg is built for its GL2 backend
+    hints.push(WindowHint::ContextVersionMajor(2));
+    hints.push(WindowHint::ContextVersionMinor(0));
+    hints
+}
+
+// every window draws into its own GL context, which shares its textures,
+// buffers and shaders with the main window's, so one nanovg context draws
+// all. the GL2 backend uses no vertex arrays, which contexts do not share
+fn draw_window(window_data: &mut WindowData, context: &types::Context) {
+    window_data.redraw = false;
+    window_data.window.make_current();
+    let (width, height) = window_data.frame_size;
+    let [red, green, blue, alpha] = window_data.clear_color;
+    unsafe {
+        ::gl::Viewport(0, 0, width, height);
+        ::gl::ClearColor(red, green, blue, alpha);
+        ::gl::Clear(::gl::COLOR_BUFFER_BIT);
+    }
+    let root_script = window_data.root_script;
+    context.ctx.frame(
+        window_data.get_window_size_float(),
+        window_data.get_pixel_ratio(),
+        |mut frame| {
+            if root_script > 0 {
+                run_scripts(window_data, root_script as u32, context, &mut frame);
+            }
+        },
+    );
+    window_data.window.swap_buffers();
 }
 
 //auto free in rust
-fn cleanup_window(_window_data: WindowData) {}
+fn cleanup_windows(_windows: Windows) {}
 
-fn is_caller_down() -> bool {
-    false
+fn max_texture_size() -> u32 {
+    let mut size = 0;
+    unsafe {
+        ::gl::GetIntegerv(::gl::MAX_TEXTURE_SIZE, &mut size);
+    }
+    size as u32
 }
@@ -0,0 +1,197 @@
+use crate::comms::*;
+use glfw::{Glfw, Monitor, MonitorCallback, MonitorEvent, VidMode, Window};
+use std::io::Write;
+use crate::util::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    // of the top left corner on the virtual desktop, in screen coordinates
+    pub pos: (i32, i32),
+    // in millimetres, 0 when the platform does not know
+    pub physical_size: (i32, i32),
+    // from the pixel density, see dpi_scale
+    pub content_scale: (f32, f32),
+    pub video_mode: Option<VidMode>,
+    pub video_modes: Vec<VidMode>,
+}
+
+pub fn monitor_info(monitor: &Monitor) -> MonitorInfo {
+    let scale = monitor_scale(monitor);
+    MonitorInfo {
+        name: monitor.get_name().unwrap_or_default(),
+        pos: monitor.get_pos(),
+        physical_size: monitor.get_physical_size(),
+        content_scale: (scale, scale),
+        video_mode: monitor.get_video_mode(),
+        video_modes: monitor.get_video_modes(),
+    }
+}
+
+// GLFW 3.2 has no content scale, so it is worked out from the pixel density,
+// where 96 dpi is 1.0. it is rounded to a quarter like the platforms' own
+// scale settings, and a monitor that does not know its size counts as 1.0
+pub fn dpi_scale(width: u32, width_mm: i32) -> f32 {
+    if width_mm <= 0 {
+        return 1.0;
+    }
+    let dpi = width as f32 * 25.4 / width_mm as f32;
+    ((dpi / 96.0 * 4.0).round() / 4.0).max(1.0)
+}
+
+pub fn monitor_scale(monitor: &Monitor) -> f32 {
+    match monitor.get_video_mode() {
+        Some(mode) => dpi_scale(mode.width, monitor.get_physical_size().0),
+        None => 1.0,
+    }
+}
+
+// of the monitor under the middle of the window, the primary one if none is
+pub fn window_monitor_scale(glfw: &mut Glfw, window: &Window) -> f32 {
+    let (x, y) = window.get_pos();
+    let (width, height) = window.get_size();
+    let (x, y) = (x + width / 2, y + height / 2);
+    glfw.with_connected_monitors(|_, monitors| {
+        let under = monitors.iter().find(|monitor| {
+            let (left, top) = monitor.get_pos();
+            match monitor.get_video_mode() {
+                Some(mode) => {
+                    x >= left
+                        && x < left + mode.width as i32
+                        && y >= top
+                        && y < top + mode.height as i32
+                }
+                None => false,
+            }
+        });
+        under.or(monitors.first()).map(monitor_scale).unwrap_or(1.0)
+    })
+}
+
+// the primary monitor comes first
+pub fn connected_monitors(glfw: &mut Glfw) -> Vec<MonitorInfo> {
+    glfw.with_connected_monitors(|_, monitors| monitors.iter().map(monitor_info).collect())
+}
+
+// MSG_OUT_MONITORS body: the monitor count, then per monitor the name as a
+// length-prefixed string, x, y, width and height in mm, x and y content
+// scale, the current video mode and a count of available video modes. a
+// video mode is width, height and refresh rate, all 0 if unknown
+pub fn write_monitors(mut w: &mut Write, monitors: &[MonitorInfo]) -> ::std::io::Result<()> {
+    write_multi!(w, monitors.len() as u32)?;
+    for monitor in monitors {
+        write_multi!(w, monitor.name.len() as u32)?;
+        w.write_all(monitor.name.as_bytes())?;
+        let (x, y) = monitor.pos;
+        let (width_mm, height_mm) = monitor.physical_size;
+        let (xscale, yscale) = monitor.content_scale;
+        write_multi!(w, x, y, width_mm, height_mm, xscale, yscale)?;
+        write_video_mode(w, monitor.video_mode.as_ref())?;
+        // glfw lists the same size and rate once per colour depth
+        let mut modes: Vec<(u32, u32, u32)> = monitor
+            .video_modes
+            .iter()
+            .map(|mode| (mode.width, mode.height, mode.refresh_rate))
+            .collect();
+        modes.dedup();
+        write_multi!(w, modes.len() as u32)?;
+        for (width, height, refresh_rate) in modes {
+            write_multi!(w, width, height, refresh_rate)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_video_mode(mut w: &mut Write, mode: Option<&VidMode>) -> ::std::io::Result<()> {
+    match mode {
+        Some(mode) => write_multi!(w, mode.width, mode.height, mode.refresh_rate),
+        None => write_multi!(w, 0u32, 0u32, 0u32),
+    }
+}
+
+// MSG_OUT_MONITOR_EVENT goes out whenever a monitor is plugged in or removed
+pub fn watch_monitors(glfw: &mut Glfw) {
+    glfw.set_monitor_callback(Some(MonitorCallback {
+        f: monitor_callback,
+        data: (),
+    }));
+}
+
+fn monitor_callback(monitor: Monitor, event: MonitorEvent, _: &()) {
+    let connected = match event {
+        MonitorEvent::Connected => 1,
+        MonitorEvent::Disconnected => 0,
+    };
+    send_monitor_event(connected, monitor.get_name().unwrap_or_default());
+}
+
+#[test]
+fn dpi_scale_test() {
+    // 24" 1080p, 27" 4K, 13" 1440p
+    assert_eq!(dpi_scale(1920, 531), 1.0);
+    assert_eq!(dpi_scale(3840, 597), 1.75);
+    assert_eq!(dpi_scale(2560, 286), 2.25);
+    // never below 1.0, and 1.0 when the size is unknown
+    assert_eq!(dpi_scale(1920, 698), 1.0);
+    assert_eq!(dpi_scale(1920, 0), 1.0);
+}
+
+#[test]
+fn write_monitors_test() {
+    use byteorder::{NativeEndian, ReadBytesExt};
+    use std::io::Cursor;
+    let mode = |width, height, refresh_rate, bits| VidMode {
+        width: width,
+        height: height,
+        red_bits: bits,
+        green_bits: bits,
+        blue_bits: bits,
+        refresh_rate: refresh_rate,
+    };
+    let monitors = vec![
+        MonitorInfo {
+            name: "DELL U2720Q".to_string(),
+            pos: (0, 0),
+            physical_size: (597, 336),
+            content_scale: (2.0, 2.0),
+            video_mode: Some(mode(3840, 2160, 60, 8)),
+            video_modes: vec![
+                mode(1920, 1080, 60, 6),
+                mode(1920, 1080, 60, 8),
+                mode(3840, 2160, 60, 8),
+            ],
+        },
+        MonitorInfo {
+            name: "".to_string(),
+            pos: (3840, -200),
+            physical_size: (0, 0),
+            content_scale: (1.0, 1.0),
+            video_mode: None,
+            video_modes: vec![],
+        },
+    ];
+    let mut buf: Vec<u8> = vec![];
+    write_monitors(&mut buf, &monitors).unwrap();
+    let mut r = Cursor::new(buf);
+    assert_eq!(read_multi!(r, u32).unwrap(), 2);
+
+    let len = read_multi!(r, usize).unwrap();
+    assert_eq!(read_string(&mut r, len).unwrap(), "DELL U2720Q");
+    assert_eq!(
+        read_multi!(r, i32, i32, i32, i32, f32, f32).unwrap(),
+        (0, 0, 597, 336, 2.0, 2.0)
+    );
+    assert_eq!(read_multi!(r, u32, u32, u32).unwrap(), (3840, 2160, 60));
+    // the two depths of 1920x1080 are listed once
+    assert_eq!(read_multi!(r, u32).unwrap(), 2);
+    assert_eq!(read_multi!(r, u32, u32, u32).unwrap(), (1920, 1080, 60));
+    assert_eq!(read_multi!(r, u32, u32, u32).unwrap(), (3840, 2160, 60));
+
+    assert_eq!(read_multi!(r, usize).unwrap(), 0);
+    assert_eq!(
+        read_multi!(r, i32, i32, i32, i32, f32, f32).unwrap(),
+        (3840, -200, 0, 0, 1.0, 1.0)
+    );
+    assert_eq!(read_multi!(r, u32, u32, u32, u32).unwrap(), (0, 0, 0, 0));
+    assert_eq!(r.position() as usize, r.get_ref().len());
+}
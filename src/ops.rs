@@ -0,0 +1,807 @@
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crate::defines::*;
+use crate::error::ProtocolError;
+use std::ffi::CString;
+use std::io::{Cursor, Write};
+use crate::util::*;
+
+type IOResult<T = ()> = ::std::io::Result<T>;
+
+pub type Rgba = [u8; 4];
+
+// every op Op::decode understands, reported in MSG_OUT_CAPABILITIES
+pub const SUPPORTED_OPS: &[u32] = &[
+    OP_PUSH_STATE,
+    OP_POP_STATE,
+    OP_RESET_STATE,
+    OP_RUN_SCRIPT,
+    OP_PAINT_LINEAR,
+    OP_PAINT_BOX,
+    OP_PAINT_RADIAL,
+    OP_PAINT_IMAGE,
+    OP_ANTI_ALIAS,
+    OP_PAINT_IMAGE_ID,
+    OP_STROKE_WIDTH,
+    OP_STROKE_COLOR,
+    OP_STROKE_PAINT,
+    OP_FILL_COLOR,
+    OP_FILL_PAINT,
+    OP_MITER_LIMIT,
+    OP_LINE_CAP,
+    OP_LINE_JOIN,
+    OP_GLOBAL_ALPHA,
+    OP_SCISSOR,
+    OP_INTERSECT_SCISSOR,
+    OP_RESET_SCISSOR,
+    OP_PATH_BEGIN,
+    OP_PATH_MOVE_TO,
+    OP_PATH_LINE_TO,
+    OP_PATH_BEZIER_TO,
+    OP_PATH_QUADRATIC_TO,
+    OP_PATH_ARC_TO,
+    OP_PATH_CLOSE,
+    OP_PATH_WINDING,
+    OP_FILL,
+    OP_STROKE,
+    OP_TRIANGLE,
+    OP_ARC,
+    OP_RECT,
+    OP_ROUND_RECT,
+    OP_ROUND_RECT_VAR,
+    OP_ELLIPSE,
+    OP_CIRCLE,
+    OP_SECTOR,
+    OP_TEXT,
+    OP_TX_RESET,
+    OP_TX_IDENTITY,
+    OP_TX_MATRIX,
+    OP_TX_TRANSLATE,
+    OP_TX_SCALE,
+    OP_TX_ROTATE,
+    OP_TX_SKEW_X,
+    OP_TX_SKEW_Y,
+    OP_FONT,
+    OP_FONT_BLUR,
+    OP_FONT_SIZE,
+    OP_TEXT_ALIGN,
+    OP_TEXT_HEIGHT,
+    OP_FONT_ID,
+    OP_TERMINATE,
+];
+
+// one decoded script op. scripts are decoded once when they are uploaded,
+// so the renderer only has to walk this list every frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    PushState,
+    PopState,
+    ResetState,
+    RunScript(u32),
+    PaintLinear {
+        start: (f32, f32),
+        end: (f32, f32),
+        start_color: Rgba,
+        end_color: Rgba,
+    },
+    PaintBox {
+        position: (f32, f32),
+        size: (f32, f32),
+        radius: f32,
+        feather: f32,
+        start_color: Rgba,
+        end_color: Rgba,
+    },
+    PaintRadial {
+        center: (f32, f32),
+        inner_radius: f32,
+        outer_radius: f32,
+        start_color: Rgba,
+        end_color: Rgba,
+    },
+    PaintImage {
+        origin: (f32, f32),
+        size: (f32, f32),
+        angle: f32,
+        alpha: u32,
+        key: String,
+    },
+    AntiAlias(i32),
+    PaintImageId {
+        origin: (f32, f32),
+        size: (f32, f32),
+        angle: f32,
+        alpha: u32,
+        handle: u32,
+    },
+    StrokeWidth(f32),
+    StrokeColor(Rgba),
+    StrokePaint,
+    FillColor(Rgba),
+    FillPaint,
+    MiterLimit(f32),
+    LineCap(i32),
+    LineJoin(i32),
+    GlobalAlpha(f32),
+    Scissor(f32, f32),
+    IntersectScissor(f32, f32),
+    ResetScissor,
+    PathBegin,
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    BezierTo([f32; 6]),
+    QuadraticTo([f32; 4]),
+    ArcTo([f32; 5]),
+    PathClose,
+    PathWinding(bool),
+    Fill,
+    Stroke,
+    Triangle([f32; 6]),
+    Arc {
+        radius: f32,
+        start: f32,
+        finish: f32,
+    },
+    Rect(f32, f32),
+    RoundRect(f32, f32, f32),
+    // takes no arguments and draws nothing, as in the C driver
+    RoundRectVar,
+    Ellipse(f32, f32),
+    Circle(f32),
+    Sector {
+        radius: f32,
+        start: f32,
+        finish: f32,
+    },
+    Text(Vec<u8>),
+    TxReset,
+    TxIdentity,
+    TxMatrix([f32; 6]),
+    TxTranslate(f32, f32),
+    TxScale(f32, f32),
+    TxRotate(f32),
+    TxSkewX(f32),
+    TxSkewY(f32),
+    Font(CString),
+    FontId(u32),
+    FontBlur(f32),
+    FontSize(f32),
+    TextAlign(i32),
+    TextHeight(f32),
+    Terminate,
+}
+
+macro_rules! read_f32s {
+    ($read:ident, $n:expr) => {
+        (Ok(()) as IOResult).and_then(|_| {
+            let mut values = [0f32; $n];
+            $read.read_f32_into::<NativeEndian>(&mut values)?;
+            Ok(values)
+        })
+    };
+}
+
+fn read_rgba(read: &mut impl ReadBytesExt) -> IOResult<Rgba> {
+    let (r, g, b, a) = read_multi!(read, u32, u32, u32, u32)?;
+    Ok([r as u8, g as u8, b as u8, a as u8])
+}
+
+fn invalid_data(reason: impl ToString) -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::InvalidData, reason.to_string())
+}
+
+impl Op {
+    pub fn id(&self) -> u32 {
+        use self::Op::*;
+        match self {
+            PushState => OP_PUSH_STATE,
+            PopState => OP_POP_STATE,
+            ResetState => OP_RESET_STATE,
+            RunScript(_) => OP_RUN_SCRIPT,
+            PaintLinear { .. } => OP_PAINT_LINEAR,
+            PaintBox { .. } => OP_PAINT_BOX,
+            PaintRadial { .. } => OP_PAINT_RADIAL,
+            PaintImage { .. } => OP_PAINT_IMAGE,
+            AntiAlias(_) => OP_ANTI_ALIAS,
+            PaintImageId { .. } => OP_PAINT_IMAGE_ID,
+            StrokeWidth(_) => OP_STROKE_WIDTH,
+            StrokeColor(_) => OP_STROKE_COLOR,
+            StrokePaint => OP_STROKE_PAINT,
+            FillColor(_) => OP_FILL_COLOR,
+            FillPaint => OP_FILL_PAINT,
+            MiterLimit(_) => OP_MITER_LIMIT,
+            LineCap(_) => OP_LINE_CAP,
+            LineJoin(_) => OP_LINE_JOIN,
+            GlobalAlpha(_) => OP_GLOBAL_ALPHA,
+            Scissor(..) => OP_SCISSOR,
+            IntersectScissor(..) => OP_INTERSECT_SCISSOR,
+            ResetScissor => OP_RESET_SCISSOR,
+            PathBegin => OP_PATH_BEGIN,
+            MoveTo(..) => OP_PATH_MOVE_TO,
+            LineTo(..) => OP_PATH_LINE_TO,
+            BezierTo(_) => OP_PATH_BEZIER_TO,
+            QuadraticTo(_) => OP_PATH_QUADRATIC_TO,
+            ArcTo(_) => OP_PATH_ARC_TO,
+            PathClose => OP_PATH_CLOSE,
+            PathWinding(_) => OP_PATH_WINDING,
+            Fill => OP_FILL,
+            Stroke => OP_STROKE,
+            Triangle(_) => OP_TRIANGLE,
+            Arc { .. } => OP_ARC,
+            Rect(..) => OP_RECT,
+            RoundRect(..) => OP_ROUND_RECT,
+            RoundRectVar => OP_ROUND_RECT_VAR,
+            Ellipse(..) => OP_ELLIPSE,
+            Circle(_) => OP_CIRCLE,
+            Sector { .. } => OP_SECTOR,
+            Text(_) => OP_TEXT,
+            TxReset => OP_TX_RESET,
+            TxIdentity => OP_TX_IDENTITY,
+            TxMatrix(_) => OP_TX_MATRIX,
+            TxTranslate(..) => OP_TX_TRANSLATE,
+            TxScale(..) => OP_TX_SCALE,
+            TxRotate(_) => OP_TX_ROTATE,
+            TxSkewX(_) => OP_TX_SKEW_X,
+            TxSkewY(_) => OP_TX_SKEW_Y,
+            Font(_) => OP_FONT,
+            FontId(_) => OP_FONT_ID,
+            FontBlur(_) => OP_FONT_BLUR,
+            FontSize(_) => OP_FONT_SIZE,
+            TextAlign(_) => OP_TEXT_ALIGN,
+            TextHeight(_) => OP_TEXT_HEIGHT,
+            Terminate => OP_TERMINATE,
+        }
+    }
+
+    fn decode(op: u32, read: &mut impl ReadBytesExt) -> IOResult<Op> {
+        use self::Op::*;
+        let re = match op {
+            // state control
+            OP_PUSH_STATE => PushState,
+            OP_POP_STATE => PopState,
+            OP_RESET_STATE => ResetState,
+
+            // script control
+            OP_RUN_SCRIPT => RunScript(read_multi!(read, u32)?),
+
+            // render styles
+            OP_PAINT_LINEAR => {
+                let (sx, sy, ex, ey) = read_multi!(read, f32, f32, f32, f32)?;
+                PaintLinear {
+                    start: (sx, sy),
+                    end: (ex, ey),
+                    start_color: read_rgba(read)?,
+                    end_color: read_rgba(read)?,
+                }
+            }
+            OP_PAINT_BOX => {
+                let (x, y, w, h, radius, feather) =
+                    read_multi!(read, f32, f32, f32, f32, f32, f32)?;
+                PaintBox {
+                    position: (x, y),
+                    size: (w, h),
+                    radius: radius,
+                    feather: feather,
+                    start_color: read_rgba(read)?,
+                    end_color: read_rgba(read)?,
+                }
+            }
+            OP_PAINT_RADIAL => {
+                let (x, y, r_in, r_out) = read_multi!(read, f32, f32, f32, f32)?;
+                PaintRadial {
+                    center: (x, y),
+                    inner_radius: r_in,
+                    outer_radius: r_out,
+                    start_color: read_rgba(read)?,
+                    end_color: read_rgba(read)?,
+                }
+            }
+            OP_PAINT_IMAGE => {
+                let (ox, oy, ex, ey, angle, alpha, key_size) =
+                    read_multi!(read, f32, f32, f32, f32, f32, u32, usize)?;
+                PaintImage {
+                    origin: (ox, oy),
+                    size: (ex, ey),
+                    angle: angle,
+                    alpha: alpha,
+                    key: read_string(read, key_size)?,
+                }
+            }
+            OP_PAINT_IMAGE_ID => {
+                let (ox, oy, ex, ey, angle, alpha, handle) =
+                    read_multi!(read, f32, f32, f32, f32, f32, u32, u32)?;
+                PaintImageId {
+                    origin: (ox, oy),
+                    size: (ex, ey),
+                    angle: angle,
+                    alpha: alpha,
+                    handle: handle,
+                }
+            }
+
+            OP_ANTI_ALIAS => AntiAlias(read_multi!(read, i32)?),
+            OP_STROKE_WIDTH => StrokeWidth(read_multi!(read, f32)?),
+            OP_STROKE_COLOR => StrokeColor(read_rgba(read)?),
+            OP_STROKE_PAINT => StrokePaint,
+            OP_FILL_COLOR => FillColor(read_rgba(read)?),
+            OP_FILL_PAINT => FillPaint,
+            OP_MITER_LIMIT => MiterLimit(read_multi!(read, f32)?),
+            OP_LINE_CAP => LineCap(read_multi!(read, i32)?),
+            OP_LINE_JOIN => LineJoin(read_multi!(read, i32)?),
+            OP_GLOBAL_ALPHA => GlobalAlpha(read_multi!(read, f32)?),
+
+            // scissoring
+            OP_SCISSOR => {
+                let (w, h) = read_multi!(read, f32, f32)?;
+                Scissor(w, h)
+            }
+            OP_INTERSECT_SCISSOR => {
+                let (w, h) = read_multi!(read, f32, f32)?;
+                IntersectScissor(w, h)
+            }
+            OP_RESET_SCISSOR => ResetScissor,
+
+            // path operations
+            OP_PATH_BEGIN => PathBegin,
+            OP_PATH_MOVE_TO => {
+                let (x, y) = read_multi!(read, f32, f32)?;
+                MoveTo(x, y)
+            }
+            OP_PATH_LINE_TO => {
+                let (x, y) = read_multi!(read, f32, f32)?;
+                LineTo(x, y)
+            }
+            OP_PATH_BEZIER_TO => BezierTo(read_f32s!(read, 6)?),
+            OP_PATH_QUADRATIC_TO => QuadraticTo(read_f32s!(read, 4)?),
+            OP_PATH_ARC_TO => ArcTo(read_f32s!(read, 5)?),
+            OP_PATH_CLOSE => PathClose,
+            OP_PATH_WINDING => PathWinding(read_multi!(read, bool)?),
+
+            OP_FILL => Fill,
+            OP_STROKE => Stroke,
+
+            OP_TRIANGLE => Triangle(read_f32s!(read, 6)?),
+            OP_ARC => {
+                let (radius, start, finish) = read_multi!(read, f32, f32, f32)?;
+                Arc {
+                    radius: radius,
+                    start: start,
+                    finish: finish,
+                }
+            }
+            OP_RECT => {
+                let (w, h) = read_multi!(read, f32, f32)?;
+                Rect(w, h)
+            }
+            OP_ROUND_RECT => {
+                let (w, h, r) = read_multi!(read, f32, f32, f32)?;
+                RoundRect(w, h, r)
+            }
+            OP_ROUND_RECT_VAR => RoundRectVar,
+            OP_ELLIPSE => {
+                let (rx, ry) = read_multi!(read, f32, f32)?;
+                Ellipse(rx, ry)
+            }
+            OP_CIRCLE => Circle(read_multi!(read, f32)?),
+            OP_SECTOR => {
+                let (radius, start, finish) = read_multi!(read, f32, f32, f32)?;
+                Sector {
+                    radius: radius,
+                    start: start,
+                    finish: finish,
+                }
+            }
+            OP_TEXT => {
+                let len = read_multi!(read, usize)?;
+                Text(read_bytes(read, len)?)
+            }
+
+            // transform operations
+            OP_TX_RESET => TxReset,
+            OP_TX_IDENTITY => TxIdentity,
+            OP_TX_MATRIX => TxMatrix(read_f32s!(read, 6)?),
+            OP_TX_TRANSLATE => {
+                let (x, y) = read_multi!(read, f32, f32)?;
+                TxTranslate(x, y)
+            }
+            OP_TX_SCALE => {
+                let (x, y) = read_multi!(read, f32, f32)?;
+                TxScale(x, y)
+            }
+            OP_TX_ROTATE => TxRotate(read_multi!(read, f32)?),
+            OP_TX_SKEW_X => TxSkewX(read_multi!(read, f32)?),
+            OP_TX_SKEW_Y => TxSkewY(read_multi!(read, f32)?),
+
+            // font styles
+            OP_FONT => {
+                let len = read_multi!(read, usize)?;
+                Font(CString::new(read_string(read, len)?).map_err(invalid_data)?)
+            }
+            OP_FONT_ID => FontId(read_multi!(read, u32)?),
+            OP_FONT_BLUR => FontBlur(read_multi!(read, f32)?),
+            OP_FONT_SIZE => FontSize(read_multi!(read, f32)?),
+            OP_TEXT_ALIGN => TextAlign(read_multi!(read, i32)?),
+            OP_TEXT_HEIGHT => TextHeight(read_multi!(read, f32)?),
+            OP_TERMINATE => Terminate,
+            _ => return Err(invalid_data("unknown script op")),
+        };
+        Ok(re)
+    }
+
+    fn encode(&self, mut w: &mut Vec<u8>) -> IOResult {
+        use self::Op::*;
+        write_multi!(w, self.id())?;
+        match self {
+            RunScript(id) => write_multi!(w, *id),
+            PaintLinear {
+                start,
+                end,
+                start_color,
+                end_color,
+            } => {
+                write_multi!(w, start.0, start.1, end.0, end.1)?;
+                write_rgba(w, start_color)?;
+                write_rgba(w, end_color)
+            }
+            PaintBox {
+                position,
+                size,
+                radius,
+                feather,
+                start_color,
+                end_color,
+            } => {
+                write_multi!(w, position.0, position.1, size.0, size.1, *radius, *feather)?;
+                write_rgba(w, start_color)?;
+                write_rgba(w, end_color)
+            }
+            PaintRadial {
+                center,
+                inner_radius,
+                outer_radius,
+                start_color,
+                end_color,
+            } => {
+                write_multi!(w, center.0, center.1, *inner_radius, *outer_radius)?;
+                write_rgba(w, start_color)?;
+                write_rgba(w, end_color)
+            }
+            PaintImage {
+                origin,
+                size,
+                angle,
+                alpha,
+                key,
+            } => {
+                write_multi!(
+                    w,
+                    origin.0,
+                    origin.1,
+                    size.0,
+                    size.1,
+                    *angle,
+                    *alpha,
+                    key.len() as u32
+                )?;
+                w.write_all(key.as_bytes())
+            }
+            PaintImageId {
+                origin,
+                size,
+                angle,
+                alpha,
+                handle,
+            } => write_multi!(
+                w,
+                origin.0,
+                origin.1,
+                size.0,
+                size.1,
+                *angle,
+                *alpha,
+                *handle
+            ),
+            AntiAlias(v) | LineCap(v) | LineJoin(v) | TextAlign(v) => write_multi!(w, *v),
+            StrokeWidth(v) | MiterLimit(v) | GlobalAlpha(v) | Circle(v) | TxRotate(v)
+            | TxSkewX(v) | TxSkewY(v) | FontBlur(v) | FontSize(v) | TextHeight(v) => {
+                write_multi!(w, *v)
+            }
+            StrokeColor(color) | FillColor(color) => write_rgba(w, color),
+            Scissor(a, b) | IntersectScissor(a, b) | MoveTo(a, b) | LineTo(a, b) | Rect(a, b)
+            | Ellipse(a, b) | TxTranslate(a, b) | TxScale(a, b) => write_multi!(w, *a, *b),
+            RoundRect(a, b, c) => write_multi!(w, *a, *b, *c),
+            BezierTo(v) | Triangle(v) | TxMatrix(v) => write_f32s(w, v),
+            QuadraticTo(v) => write_f32s(w, v),
+            ArcTo(v) => write_f32s(w, v),
+            PathWinding(v) => write_multi!(w, *v),
+            FontId(handle) => write_multi!(w, *handle),
+            Arc {
+                radius,
+                start,
+                finish,
+            }
+            | Sector {
+                radius,
+                start,
+                finish,
+            } => write_multi!(w, *radius, *start, *finish),
+            Text(text) => {
+                write_multi!(w, text.len() as u32)?;
+                w.write_all(text)
+            }
+            Font(name) => {
+                write_multi!(w, name.as_bytes().len() as u32)?;
+                w.write_all(name.as_bytes())
+            }
+            PushState | PopState | ResetState | StrokePaint | FillPaint | ResetScissor
+            | PathBegin | PathClose | Fill | Stroke | RoundRectVar | TxReset | TxIdentity
+            | Terminate => Ok(()),
+        }
+    }
+}
+
+fn write_rgba(w: &mut Vec<u8>, color: &Rgba) -> IOResult {
+    for c in color {
+        w.write_u32::<NativeEndian>(*c as u32)?;
+    }
+    Ok(())
+}
+
+fn write_f32s(w: &mut Vec<u8>, values: &[f32]) -> IOResult {
+    for v in values {
+        w.write_f32::<NativeEndian>(*v)?;
+    }
+    Ok(())
+}
+
+// decodes and validates a whole script. errors carry the op being decoded
+// and the byte offset into the script where decoding stopped
+pub fn decode_script(script: &[u8]) -> Result<Vec<Op>, ProtocolError> {
+    let mut read = Cursor::new(script);
+    let mut ops = vec![];
+    while read.position() < script.len() as u64 {
+        let op = read_multi!(read, u32)
+            .map_err(|err| ProtocolError::new(0, read.position(), err))?;
+        let decoded =
+            Op::decode(op, &mut read).map_err(|err| ProtocolError::new(op, read.position(), err))?;
+        let done = decoded == Op::Terminate;
+        ops.push(decoded);
+        if done {
+            break;
+        }
+    }
+    Ok(ops)
+}
+
+pub struct OpSplice {
+    pub start: usize,
+    pub remove: usize,
+    pub ops: Vec<Op>,
+}
+
+// applies the splices in order, each against the result of the previous
+// one. all ranges are checked first so a bad patch leaves the script as it was
+pub fn splice_script(script: &mut Vec<Op>, splices: Vec<OpSplice>) -> Result<(), ProtocolError> {
+    let mut len = script.len();
+    for splice in &splices {
+        if splice.start > len || splice.remove > len - splice.start {
+            return Err(ProtocolError::new(
+                CMD_UPDATE_GRAPH,
+                splice.start as u64,
+                format!(
+                    "cannot replace {} ops at {} in a script of {}",
+                    splice.remove, splice.start, len
+                ),
+            ));
+        }
+        len = len - splice.remove + splice.ops.len();
+    }
+    for splice in splices {
+        let end = splice.start + splice.remove;
+        script.splice(splice.start..end, splice.ops);
+    }
+    Ok(())
+}
+
+#[allow(unused)]
+pub fn encode_script(ops: &[Op]) -> Vec<u8> {
+    let mut buf = vec![];
+    for op in ops {
+        op.encode(&mut buf).expect("writing to a Vec never fails");
+    }
+    buf
+}
+
+#[test]
+fn script_round_trip_test() {
+    let ops = vec![
+        Op::PushState,
+        Op::RunScript(3),
+        Op::PaintLinear {
+            start: (0.0, 1.0),
+            end: (2.0, 3.0),
+            start_color: [1, 2, 3, 4],
+            end_color: [5, 6, 7, 8],
+        },
+        Op::PaintBox {
+            position: (0.0, 1.0),
+            size: (10.0, 20.0),
+            radius: 2.0,
+            feather: 3.0,
+            start_color: [1, 2, 3, 4],
+            end_color: [5, 6, 7, 8],
+        },
+        Op::PaintRadial {
+            center: (5.0, 5.0),
+            inner_radius: 1.0,
+            outer_radius: 4.0,
+            start_color: [1, 2, 3, 4],
+            end_color: [5, 6, 7, 8],
+        },
+        Op::PaintImage {
+            origin: (0.0, 0.0),
+            size: (0.0, 0.0),
+            angle: 0.5,
+            alpha: 255,
+            key: "tx_key".to_string(),
+        },
+        Op::AntiAlias(1),
+        Op::PaintImageId {
+            origin: (1.0, 2.0),
+            size: (3.0, 4.0),
+            angle: 0.0,
+            alpha: 128,
+            handle: 3,
+        },
+        Op::StrokeWidth(2.0),
+        Op::StrokeColor([255, 0, 0, 255]),
+        Op::StrokePaint,
+        Op::FillColor([0, 255, 0, 255]),
+        Op::FillPaint,
+        Op::MiterLimit(4.0),
+        Op::LineCap(1),
+        Op::LineJoin(2),
+        Op::GlobalAlpha(0.5),
+        Op::Scissor(10.0, 20.0),
+        Op::IntersectScissor(5.0, 6.0),
+        Op::ResetScissor,
+        Op::PathBegin,
+        Op::MoveTo(1.0, 2.0),
+        Op::LineTo(3.0, 4.0),
+        Op::BezierTo([1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        Op::QuadraticTo([1.0, 2.0, 3.0, 4.0]),
+        Op::ArcTo([1.0, 2.0, 3.0, 4.0, 5.0]),
+        Op::PathClose,
+        Op::PathWinding(true),
+        Op::Fill,
+        Op::Stroke,
+        Op::Triangle([0.0, 0.0, 1.0, 0.0, 0.0, 1.0]),
+        Op::Arc {
+            radius: 10.0,
+            start: 0.0,
+            finish: 1.0,
+        },
+        Op::Rect(10.0, 20.0),
+        Op::RoundRect(10.0, 20.0, 3.0),
+        Op::RoundRectVar,
+        Op::Ellipse(5.0, 6.0),
+        Op::Circle(7.0),
+        Op::Sector {
+            radius: 10.0,
+            start: 0.0,
+            finish: 2.0,
+        },
+        Op::Text(b"hello".to_vec()),
+        Op::TxReset,
+        Op::TxIdentity,
+        Op::TxMatrix([1.0, 0.0, 0.0, 1.0, 5.0, 6.0]),
+        Op::TxTranslate(1.0, 2.0),
+        Op::TxScale(2.0, 2.0),
+        Op::TxRotate(0.5),
+        Op::TxSkewX(0.1),
+        Op::TxSkewY(0.2),
+        Op::Font(CString::new("roboto").unwrap()),
+        Op::FontId(2),
+        Op::FontBlur(1.0),
+        Op::FontSize(20.0),
+        Op::TextAlign(1),
+        Op::TextHeight(1.5),
+        Op::PopState,
+        Op::ResetState,
+        Op::Terminate,
+    ];
+    let bytes = encode_script(&ops);
+    assert_eq!(decode_script(&bytes).expect("decode failed"), ops);
+
+    let mut ids: Vec<u32> = ops.iter().map(|op| op.id()).collect();
+    ids.sort();
+    ids.dedup();
+    let mut supported = SUPPORTED_OPS.to_vec();
+    supported.sort();
+    assert_eq!(ids, supported);
+}
+
+#[test]
+fn script_decode_error_test() {
+    let mut bytes = encode_script(&[Op::PathBegin, Op::MoveTo(1.0, 2.0)]);
+    bytes.truncate(bytes.len() - 2);
+    let err = decode_script(&bytes).unwrap_err();
+    assert_eq!(err.cmd, OP_PATH_MOVE_TO);
+    assert_eq!(err.offset, bytes.len() as u64);
+
+    let mut bytes = encode_script(&[Op::PathBegin]);
+    write_multi!(bytes, 0xABCDu32).unwrap();
+    let err = decode_script(&bytes).unwrap_err();
+    assert_eq!(err.cmd, 0xABCD);
+    assert_eq!(err.offset, 8);
+}
+
+#[test]
+fn script_decode_stops_at_terminate_test() {
+    let mut bytes = encode_script(&[Op::Fill, Op::Terminate]);
+    bytes.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(decode_script(&bytes).unwrap(), vec![Op::Fill, Op::Terminate]);
+}
+
+#[test]
+fn script_round_rect_var_framing_test() {
+    // the op is its id alone, the next op follows right after it
+    let mut bytes = vec![];
+    write_multi!(bytes, OP_ROUND_RECT_VAR, OP_CIRCLE, 5.0f32, OP_TERMINATE).unwrap();
+    assert_eq!(
+        decode_script(&bytes).unwrap(),
+        vec![Op::RoundRectVar, Op::Circle(5.0), Op::Terminate]
+    );
+}
+
+#[test]
+fn splice_script_test() {
+    let mut script = vec![Op::PushState, Op::Rect(1.0, 2.0), Op::Fill, Op::Terminate];
+    let splices = vec![
+        // replace the rect
+        OpSplice {
+            start: 1,
+            remove: 1,
+            ops: vec![Op::Circle(3.0)],
+        },
+        // insert before the fill
+        OpSplice {
+            start: 2,
+            remove: 0,
+            ops: vec![Op::FillColor([1, 2, 3, 4])],
+        },
+        // drop the push
+        OpSplice {
+            start: 0,
+            remove: 1,
+            ops: vec![],
+        },
+    ];
+    splice_script(&mut script, splices).unwrap();
+    assert_eq!(
+        script,
+        vec![
+            Op::Circle(3.0),
+            Op::FillColor([1, 2, 3, 4]),
+            Op::Fill,
+            Op::Terminate
+        ]
+    );
+
+    // the second splice only fits if the first one is ignored
+    let splices = vec![
+        OpSplice {
+            start: 0,
+            remove: 2,
+            ops: vec![],
+        },
+        OpSplice {
+            start: 1,
+            remove: 2,
+            ops: vec![],
+        },
+    ];
+    let err = splice_script(&mut script, splices).unwrap_err();
+    assert_eq!(err.cmd, CMD_UPDATE_GRAPH);
+    assert_eq!(err.offset, 1);
+    assert_eq!(script.len(), 4);
+}
@@ -0,0 +1,271 @@
+use byteorder::{NativeEndian, ReadBytesExt};
+use crate::defines::*;
+use crate::liveness::mark_output_closed;
+use std::io::{self, BufWriter, Cursor, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+use crate::transport::*;
+use crate::types::*;
+use crate::util::*;
+
+// everything sent while handling one frame is queued here and handed to the
+// writer thread as one batch, so a full pipe never stalls rendering
+pub struct Outbox {
+    pending: Vec<Message>,
+    // something in pending that coalesce would leave alone
+    urgent: bool,
+    sender: Option<Sender<Vec<Message>>>,
+    thread: Option<JoinHandle<()>>,
+    pub coalesced: u32,
+}
+
+impl Outbox {
+    pub fn new() -> Outbox {
+        Outbox {
+            pending: vec![],
+            urgent: false,
+            sender: None,
+            thread: None,
+            coalesced: 0,
+        }
+    }
+
+    pub fn start(&mut self, writer: Writer) {
+        let (sender, thread) = start_write_thread(writer);
+        self.sender = Some(sender);
+        self.thread = Some(thread);
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn push(&mut self, msg: Message) {
+        self.urgent = self.urgent || !is_coalescable(&msg);
+        self.pending.push(msg);
+    }
+
+    pub fn flush_urgent(&mut self) {
+        if self.urgent {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.urgent = false;
+        if self.pending.is_empty() {
+            return;
+        }
+        let (batch, coalesced) = coalesce(::std::mem::replace(&mut self.pending, vec![]));
+        self.coalesced = self.coalesced.wrapping_add(coalesced as u32);
+        if let Some(sender) = self.sender.as_ref() {
+            // a dead writer thread already marked the output closed
+            let _ = sender.send(batch);
+        }
+    }
+
+    // flushes and waits until everything queued has been written
+    pub fn close(&mut self) {
+        self.flush();
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn start_write_thread(writer: Writer) -> (Sender<Vec<Message>>, JoinHandle<()>) {
+    let (sender, receiver) = channel::<Vec<Message>>();
+    let thread = ::std::thread::spawn(move || {
+        let mut out = BufWriter::new(writer);
+        while let Ok(batch) = receiver.recv() {
+            // take whatever else piled up while we were blocked on the last flush
+            let mut written = write_batch(&mut out, &batch);
+            for batch in receiver.try_iter() {
+                written = written.and_then(|_| write_batch(&mut out, &batch));
+            }
+            if written.and_then(|_| out.flush()).is_err() {
+                mark_output_closed();
+                break;
+            }
+        }
+    });
+    (sender, thread)
+}
+
+fn write_batch(out: &mut impl Write, batch: &[Message]) -> io::Result<()> {
+    for msg in batch {
+        write_frame(out, msg)?;
+    }
+    Ok(())
+}
+
+pub fn msg_id(msg: &[u8]) -> Option<u32> {
+    let mut read = Cursor::new(msg);
+    read_multi!(read, u32).ok()
+}
+
+// the messages coalesce may merge, which can wait for the end of the frame
+fn is_coalescable(msg: &[u8]) -> bool {
+    let (_, body) = split_window(msg);
+    matches!(
+        msg_id(body),
+        Some(MSG_OUT_CURSOR_POS)
+            | Some(MSG_OUT_WINDOW_POS)
+            | Some(MSG_OUT_MOUSE_SCROLL)
+            | Some(MSG_OUT_CURSOR_MOTION)
+    )
+}
+
+// splits off the MSG_OUT_WINDOW id that wraps messages from secondary
+// windows. the tag is empty for the main window
+fn split_window(msg: &[u8]) -> (&[u8], &[u8]) {
+    if msg.len() >= 8 && msg_id(msg) == Some(MSG_OUT_WINDOW) {
+        msg.split_at(8)
+    } else {
+        msg.split_at(0)
+    }
+}
+
+// runs of cursor or window positions collapse into the last one, runs of scrolls and
+// relative motion into one with the summed offsets. only messages from the same
+// window are merged. returns how many messages were dropped
+pub fn coalesce(batch: Vec<Message>) -> (Vec<Message>, usize) {
+    let mut out: Vec<Message> = Vec::with_capacity(batch.len());
+    let mut coalesced = 0;
+    for msg in batch {
+        if let Some(last) = out.last_mut() {
+            let (tag, body) = split_window(&msg);
+            let (last_tag, last_body) = split_window(last);
+            let id = msg_id(body);
+            if tag == last_tag && id == msg_id(last_body) {
+                let merged = match id {
+                    Some(MSG_OUT_CURSOR_POS) | Some(MSG_OUT_WINDOW_POS) => Ok(body.to_vec()),
+                    Some(MSG_OUT_MOUSE_SCROLL) => merge_scroll(last_body, body),
+                    Some(MSG_OUT_CURSOR_MOTION) => merge_motion(last_body, body),
+                    _ => Err(io::ErrorKind::InvalidData.into()),
+                };
+                if let Ok(merged) = merged {
+                    *last = [tag, &merged[..]].concat();
+                    coalesced += 1;
+                    continue;
+                }
+            }
+        }
+        out.push(msg);
+    }
+    (out, coalesced)
+}
+
+fn merge_scroll(last: &[u8], next: &[u8]) -> io::Result<Message> {
+    let mut read = Cursor::new(last);
+    let (_, xoffset, yoffset) = read_multi!(read, u32, f32, f32)?;
+    let mut read = Cursor::new(next);
+    let (_, next_xoffset, next_yoffset, xpos, ypos) = read_multi!(read, u32, f32, f32, f32, f32)?;
+    let mut w: Message = Vec::with_capacity(next.len());
+    write_multi!(
+        w,
+        MSG_OUT_MOUSE_SCROLL,
+        xoffset + next_xoffset,
+        yoffset + next_yoffset,
+        xpos,
+        ypos
+    )?;
+    Ok(w)
+}
+
+fn merge_motion(last: &[u8], next: &[u8]) -> io::Result<Message> {
+    let mut read = Cursor::new(last);
+    let (_, dx, dy) = read_multi!(read, u32, f32, f32)?;
+    let mut read = Cursor::new(next);
+    let (_, next_dx, next_dy) = read_multi!(read, u32, f32, f32)?;
+    let mut w: Message = Vec::with_capacity(next.len());
+    write_multi!(w, MSG_OUT_CURSOR_MOTION, dx + next_dx, dy + next_dy)?;
+    Ok(w)
+}
+
+#[test]
+fn outbox_urgent_test() {
+    let msg = |id: u32| {
+        let mut w: Message = vec![];
+        write_multi!(w, id, 1.0f32, 2.0f32).unwrap();
+        w
+    };
+    let mut outbox = Outbox::new();
+    outbox.push(msg(MSG_OUT_CURSOR_POS));
+    outbox.push(msg(MSG_OUT_MOUSE_SCROLL));
+    assert!(!outbox.urgent);
+    outbox.push(msg(MSG_OUT_DRAW_READY));
+    assert!(outbox.urgent);
+    outbox.flush_urgent();
+    assert!(!outbox.urgent);
+    assert!(outbox.pending.is_empty());
+}
+
+#[test]
+fn coalesce_test() {
+    let msg = |id: u32, values: &[f32]| {
+        let mut w: Message = vec![];
+        write_multi!(w, id).unwrap();
+        for value in values {
+            write_multi!(w, *value).unwrap();
+        }
+        w
+    };
+    let batch = vec![
+        msg(MSG_OUT_CURSOR_POS, &[1.0, 1.0]),
+        msg(MSG_OUT_CURSOR_POS, &[2.0, 2.0]),
+        msg(MSG_OUT_CURSOR_POS, &[3.0, 3.0]),
+        msg(MSG_OUT_MOUSE_SCROLL, &[0.0, 1.0, 3.0, 3.0]),
+        msg(MSG_OUT_MOUSE_SCROLL, &[0.5, 2.0, 4.0, 4.0]),
+        msg(MSG_OUT_CURSOR_POS, &[4.0, 4.0]),
+        msg(MSG_OUT_MOUSE_BUTTON, &[]),
+        msg(MSG_OUT_CURSOR_POS, &[5.0, 5.0]),
+        msg(MSG_OUT_CURSOR_MOTION, &[1.0, -1.0]),
+        msg(MSG_OUT_CURSOR_MOTION, &[2.5, 0.0]),
+    ];
+    let (out, coalesced) = coalesce(batch);
+    assert_eq!(coalesced, 4);
+    assert_eq!(
+        out,
+        vec![
+            msg(MSG_OUT_CURSOR_POS, &[3.0, 3.0]),
+            msg(MSG_OUT_MOUSE_SCROLL, &[0.5, 3.0, 4.0, 4.0]),
+            msg(MSG_OUT_CURSOR_POS, &[4.0, 4.0]),
+            msg(MSG_OUT_MOUSE_BUTTON, &[]),
+            msg(MSG_OUT_CURSOR_POS, &[5.0, 5.0]),
+            msg(MSG_OUT_CURSOR_MOTION, &[3.5, -1.0]),
+        ]
+    );
+
+    // secondary windows only merge with themselves
+    let window = |window: u32, inner: Message| {
+        let mut w: Message = vec![];
+        write_multi!(w, MSG_OUT_WINDOW, window).unwrap();
+        w.extend(inner);
+        w
+    };
+    let batch = vec![
+        window(2, msg(MSG_OUT_CURSOR_POS, &[1.0, 1.0])),
+        window(2, msg(MSG_OUT_CURSOR_POS, &[2.0, 2.0])),
+        window(3, msg(MSG_OUT_CURSOR_POS, &[3.0, 3.0])),
+        msg(MSG_OUT_CURSOR_POS, &[4.0, 4.0]),
+        window(3, msg(MSG_OUT_MOUSE_SCROLL, &[0.0, 1.0, 3.0, 3.0])),
+        window(3, msg(MSG_OUT_MOUSE_SCROLL, &[0.5, 2.0, 4.0, 4.0])),
+        window(3, msg(MSG_OUT_MOUSE_BUTTON, &[])),
+        window(3, msg(MSG_OUT_MOUSE_BUTTON, &[])),
+    ];
+    let (out, coalesced) = coalesce(batch);
+    assert_eq!(coalesced, 2);
+    assert_eq!(
+        out,
+        vec![
+            window(2, msg(MSG_OUT_CURSOR_POS, &[2.0, 2.0])),
+            window(3, msg(MSG_OUT_CURSOR_POS, &[3.0, 3.0])),
+            msg(MSG_OUT_CURSOR_POS, &[4.0, 4.0]),
+            window(3, msg(MSG_OUT_MOUSE_SCROLL, &[0.5, 3.0, 4.0, 4.0])),
+            window(3, msg(MSG_OUT_MOUSE_BUTTON, &[])),
+            window(3, msg(MSG_OUT_MOUSE_BUTTON, &[])),
+        ]
+    );
+}
@@ -1,11 +1,15 @@
-use byteorder::{NativeEndian, ReadBytesExt};
 use crate::comms::*;
 use crate::defines::*;
-use nanovg::{Color, Gradient, ImagePattern, Paint};
+use crate::error::ProtocolError;
+use nanovg::{Color, Gradient, Image, ImagePattern, Paint};
 use nanovg_sys::*;
-use std::io::Cursor;
+use crate::ops::*;
 use crate::types::*;
-use crate::util::*;
+
+enum Step<'e> {
+    Next(Option<Box<Paint + 'e>>),
+    Done,
+}
 
 pub fn run_scripts<'a, 'ctx: 'tx, 'tx>(
     window_data: &mut WindowData,
@@ -14,249 +18,336 @@ pub fn run_scripts<'a, 'ctx: 'tx, 'tx>(
     frame: &mut ::nanovg::Frame<'a>,
 ) {
     if let Some(script) = window_data.get_script(script_id) {
-        let script = script.clone();
-        let mut read = Cursor::new(script);
         let trace = false;
-        run_script_internal(window_data, &mut read, ctx, frame, None, trace).unwrap();
+        window_data.script_stack.push(script_id);
+        run_script_internal(&script, trace, |index, op, curr_paint| {
+            run_op(window_data, index, op, ctx, frame, curr_paint)
+        });
+        window_data.script_stack.pop();
     }
 }
 
-fn run_script_internal<'frame, 'ctx: 'tx, 'tx: 'e, 'e>(
+fn run_sub_script<'a, 'ctx: 'tx, 'tx>(
     window_data: &mut WindowData,
-    script: &mut impl ReadBytesExt,
-    ctx: &'e Context<'ctx, 'tx>,
-    frame: &mut ::nanovg::Frame<'frame>,
-    curr_paint: Option<Box<Paint + 'e>>,
-    trace: bool,
-) -> ::std::io::Result<()> {
-    let mut next_paint: Option<Box<Paint + 'e>> = None;
-    {
-        let op = read_multi!(script, u32)?;
-        let raw_ctx = ctx.ctx.raw();
+    script_id: u32,
+    index: usize,
+    ctx: &Context<'ctx, 'tx>,
+    frame: &mut ::nanovg::Frame<'a>,
+) {
+    match window_data.script_stack.check(script_id) {
+        Ok(()) => run_scripts(window_data, script_id, ctx, frame),
+        Err(nesting) => {
+            // skip this branch for the frame, the rest of the parent still draws
+            if window_data.script_stack.first_report(&nesting) {
+                let mut err = ProtocolError::new(OP_RUN_SCRIPT, index as u64, &nesting);
+                if let Some(parent) = window_data.script_stack.current() {
+                    err = err.in_script(parent);
+                }
+                send_error(&err);
+            }
+        }
+    }
+}
 
+// walks the ops one after another, handing each op's paint to the next one.
+// a loop rather than one call per op, so long scripts can't blow the stack
+fn run_script_internal<'e, F>(script: &[Op], trace: bool, mut run_op: F)
+where
+    F: FnMut(usize, &Op, Option<Box<Paint + 'e>>) -> Step<'e>,
+{
+    let mut curr_paint: Option<Box<Paint + 'e>> = None;
+    for (index, op) in script.iter().enumerate() {
         if trace {
-            send_puts(format!("script op: {}", op));
+            send_puts(format!("script op: {:?}", op));
         }
 
-        match op {
-            // state control
-            OP_PUSH_STATE => unsafe {
-                nvgSave(raw_ctx);
-            },
-
-            OP_POP_STATE => unsafe {
-                nvgRestore(raw_ctx);
-            },
-
-            OP_RESET_STATE => unsafe {
-                nvgReset(raw_ctx);
-            },
-
-            // script control
-            OP_RUN_SCRIPT => {
-                let script_id = read_multi!(script, u32).unwrap();
-                run_scripts(window_data, script_id, ctx, frame);
-            }
-
-            // render styles
-            OP_PAINT_LINEAR => {
-                next_paint = paint_linear(script);
-            }
-            OP_PAINT_BOX => {
-                next_paint = paint_box(script);
-            }
-            OP_PAINT_RADIAL => {
-                next_paint = paint_radial(script);
-            }
-            OP_PAINT_IMAGE => {
-                next_paint = paint_image(ctx, script);
-            }
-
-            OP_ANTI_ALIAS => shape_anti_alias(raw_ctx, script),
+        match run_op(index, op, curr_paint) {
+            Step::Next(next_paint) => curr_paint = next_paint,
+            Step::Done => break,
+        }
+    }
+}
 
-            OP_STROKE_WIDTH => shape_width(raw_ctx, script),
+fn run_op<'frame, 'ctx: 'tx, 'tx: 'e, 'e>(
+    window_data: &mut WindowData,
+    index: usize,
+    op: &Op,
+    ctx: &'e Context<'ctx, 'tx>,
+    frame: &mut ::nanovg::Frame<'frame>,
+    curr_paint: Option<Box<Paint + 'e>>,
+) -> Step<'e> {
+    let mut next_paint: Option<Box<Paint + 'e>> = None;
+    let raw_ctx = ctx.ctx.raw();
+
+    match op {
+        // state control
+        Op::PushState => unsafe {
+            nvgSave(raw_ctx);
+        },
+
+        Op::PopState => unsafe {
+            nvgRestore(raw_ctx);
+        },
+
+        Op::ResetState => unsafe {
+            nvgReset(raw_ctx);
+        },
+
+        // script control
+        Op::RunScript(script_id) => run_sub_script(window_data, *script_id, index, ctx, frame),
+
+        // render styles
+        Op::PaintLinear {
+            start,
+            end,
+            start_color,
+            end_color,
+        } => {
+            next_paint = Some(Box::new(Gradient::Linear {
+                start: *start,
+                end: *end,
+                start_color: color(start_color),
+                end_color: color(end_color),
+            }));
+        }
+        Op::PaintBox {
+            position,
+            size,
+            radius,
+            feather,
+            start_color,
+            end_color,
+        } => {
+            next_paint = Some(Box::new(Gradient::Box {
+                position: *position,
+                size: *size,
+                radius: *radius,
+                feather: *feather,
+                start_color: color(start_color),
+                end_color: color(end_color),
+            }));
+        }
+        Op::PaintRadial {
+            center,
+            inner_radius,
+            outer_radius,
+            start_color,
+            end_color,
+        } => {
+            next_paint = Some(Box::new(Gradient::Radial {
+                center: *center,
+                inner_radius: *inner_radius,
+                outer_radius: *outer_radius,
+                start_color: color(start_color),
+                end_color: color(end_color),
+            }));
+        }
+        Op::PaintImage {
+            origin,
+            size,
+            angle,
+            alpha,
+            key,
+        } => {
+            let image = ctx.cache.texture(key);
+            next_paint = paint_image(image, *origin, *size, *angle, *alpha);
+        }
+        Op::PaintImageId {
+            origin,
+            size,
+            angle,
+            alpha,
+            handle,
+        } => {
+            let image = ctx.cache.texture_by_handle(*handle);
+            next_paint = paint_image(image, *origin, *size, *angle, *alpha);
+        }
 
-            OP_STROKE_COLOR => stroke_color(raw_ctx, script),
+        Op::AntiAlias(enabled) => unsafe {
+            nvgShapeAntiAlias(raw_ctx, *enabled);
+        },
 
-            OP_STROKE_PAINT => {
-                if let Some(paint) = &curr_paint {
-                    paint.stroke(ctx.ctx);
-                }
-                next_paint = curr_paint;
-            }
+        Op::StrokeWidth(width) => unsafe {
+            nvgStrokeWidth(raw_ctx, *width);
+        },
 
-            OP_FILL_COLOR => fill_color(ctx.ctx, script),
+        Op::StrokeColor([r, g, b, a]) => unsafe {
+            nvgStrokeColor(raw_ctx, nvgRGBA(*r, *g, *b, *a));
+        },
 
-            OP_FILL_PAINT => {
-                if let Some(paint) = &curr_paint {
-                    paint.fill(ctx.ctx);
-                }
-                next_paint = curr_paint;
+        Op::StrokePaint => {
+            if let Some(paint) = &curr_paint {
+                paint.stroke(ctx.ctx);
             }
+            next_paint = curr_paint;
+        }
 
-            OP_MITER_LIMIT => miter_limit(raw_ctx, script),
-            OP_LINE_CAP => line_cap(raw_ctx, script),
-            OP_LINE_JOIN => line_join(raw_ctx, script),
-            OP_GLOBAL_ALPHA => global_alpha(raw_ctx, script),
-
-            // scissoring
-            OP_SCISSOR => scissor(raw_ctx, script),
-            OP_INTERSECT_SCISSOR => intersect_scissor(raw_ctx, script),
-
-            OP_RESET_SCISSOR => unsafe {
-                nvgResetScissor(raw_ctx);
-            },
-
-            // path operations
-            OP_PATH_BEGIN => unsafe {
-                nvgBeginPath(raw_ctx);
-            },
-
-            OP_PATH_MOVE_TO => move_to(raw_ctx, script),
-            OP_PATH_LINE_TO => line_to(raw_ctx, script),
-            OP_PATH_BEZIER_TO => bezier_to(raw_ctx, script),
-            OP_PATH_QUADRATIC_TO => quadratic_to(raw_ctx, script),
-            OP_PATH_ARC_TO => arc_to(raw_ctx, script),
-            OP_PATH_CLOSE => unsafe {
-                nvgClosePath(raw_ctx);
-            },
-            OP_PATH_WINDING => path_winding(raw_ctx, script),
-
-            OP_FILL => unsafe {
-                nvgFill(raw_ctx);
-            },
-            OP_STROKE => unsafe {
-                nvgStroke(raw_ctx);
-            },
-
-            OP_TRIANGLE => triangle(raw_ctx, script),
-            OP_ARC => arc(raw_ctx, script),
-            OP_RECT => rect(raw_ctx, script),
-            OP_ROUND_RECT => round_rect(raw_ctx, script),
-            OP_ROUND_RECT_VAR => (),
-            OP_ELLIPSE => ellipse(raw_ctx, script),
-            OP_CIRCLE => circle(raw_ctx, script),
-            OP_SECTOR => sector(raw_ctx, script),
-            OP_TEXT => text(raw_ctx, script),
-
-            // transform operations
-            OP_TX_RESET => unsafe {
-                nvgResetTransform(raw_ctx);
-            },
-            OP_TX_IDENTITY => (),
-            OP_TX_MATRIX => tx_matrix(raw_ctx, script),
-            OP_TX_TRANSLATE => tx_translate(raw_ctx, script),
-            OP_TX_SCALE => tx_scale(raw_ctx, script),
-            OP_TX_ROTATE => tx_rotate(raw_ctx, script),
-            OP_TX_SKEW_X => tx_skew_x(raw_ctx, script),
-            OP_TX_SKEW_Y => tx_skew_y(raw_ctx, script),
+        Op::FillColor(rgba) => color(rgba).fill(ctx.ctx),
 
-            //   // font styles
-            OP_FONT => font(raw_ctx, script),
-            OP_FONT_BLUR => font_blur(raw_ctx, script),
-            OP_FONT_SIZE => font_size(raw_ctx, script),
-            OP_TEXT_ALIGN => text_align(raw_ctx, script),
-            OP_TEXT_HEIGHT => text_height(raw_ctx, script),
-            OP_TERMINATE => return Ok(()),
-            _ => {
-                send_puts(format!("!!!Unknown script command: {}", op));
-                return Ok(());
+        Op::FillPaint => {
+            if let Some(paint) = &curr_paint {
+                paint.fill(ctx.ctx);
             }
+            next_paint = curr_paint;
         }
-    }
-    run_script_internal(window_data, script, ctx, frame, next_paint, trace)
-}
-fn paint_linear(script: &mut impl ReadBytesExt) -> Option<Box<Paint>> {
-    let (sx, sy, ex, ey, sc, ec) = read_multi!(script, f32, f32, f32, f32, Color, Color).unwrap();
 
-    Some(Box::new(Gradient::Linear {
-        start: (sx, sy),
-        end: (ex, ey),
-        start_color: sc,
-        end_color: ec,
-    }))
-}
-fn paint_box(script: &mut impl ReadBytesExt) -> Option<Box<Paint>> {
-    let (x, y, w, h, radius, feather, sc, ec) =
-        read_multi!(script, f32, f32, f32, f32, f32, f32, Color, Color).unwrap();
-
-    Some(Box::new(Gradient::Box {
-        position: (x, y),
-        size: (w, h),
-        radius: radius,
-        feather: feather,
-        start_color: sc,
-        end_color: ec,
-    }))
-}
-fn paint_radial(script: &mut impl ReadBytesExt) -> Option<Box<Paint>> {
-    let (x, y, r_in, r_out, sc, ec) =
-        read_multi!(script, f32, f32, f32, f32, Color, Color).unwrap();
-
-    Some(Box::new(Gradient::Radial {
-        center: (x, y),
-        inner_radius: r_in,
-        outer_radius: r_out,
-        start_color: sc,
-        end_color: ec,
-    }))
-}
-fn paint_image<'ctx: 'tx, 'tx: 'e, 'e>(
-    ctx: &'e Context<'ctx, 'tx>,
-    script: &mut impl ReadBytesExt,
+        Op::MiterLimit(limit) => unsafe {
+            nvgMiterLimit(raw_ctx, *limit);
+        },
+        Op::LineCap(cap) => unsafe {
+            nvgLineCap(raw_ctx, *cap);
+        },
+        Op::LineJoin(join) => line_join(raw_ctx, *join),
+        Op::GlobalAlpha(alpha) => unsafe {
+            nvgGlobalAlpha(raw_ctx, *alpha);
+        },
+
+        // scissoring
+        Op::Scissor(w, h) => unsafe {
+            nvgScissor(raw_ctx, 0f32, 0f32, *w, *h);
+        },
+        Op::IntersectScissor(w, h) => unsafe {
+            nvgIntersectScissor(raw_ctx, 0f32, 0f32, *w, *h);
+        },
+
+        Op::ResetScissor => unsafe {
+            nvgResetScissor(raw_ctx);
+        },
+
+        // path operations
+        Op::PathBegin => unsafe {
+            nvgBeginPath(raw_ctx);
+        },
+
+        Op::MoveTo(x, y) => unsafe {
+            nvgMoveTo(raw_ctx, *x, *y);
+        },
+        Op::LineTo(x, y) => unsafe {
+            nvgLineTo(raw_ctx, *x, *y);
+        },
+        Op::BezierTo([c1x, c1y, c2x, c2y, x, y]) => unsafe {
+            nvgBezierTo(raw_ctx, *c1x, *c1y, *c2x, *c2y, *x, *y);
+        },
+        Op::QuadraticTo([x1, y1, x2, y2]) => unsafe {
+            nvgQuadTo(raw_ctx, *x1, *y1, *x2, *y2);
+        },
+        Op::ArcTo([x1, y1, x2, y2, radius]) => unsafe {
+            nvgArcTo(raw_ctx, *x1, *y1, *x2, *y2, *radius);
+        },
+        Op::PathClose => unsafe {
+            nvgClosePath(raw_ctx);
+        },
+        Op::PathWinding(solid) => path_winding(raw_ctx, *solid),
+
+        Op::Fill => unsafe {
+            nvgFill(raw_ctx);
+        },
+        Op::Stroke => unsafe {
+            nvgStroke(raw_ctx);
+        },
+
+        Op::Triangle(points) => triangle(raw_ctx, points),
+        Op::Arc {
+            radius,
+            start,
+            finish,
+        } => arc(raw_ctx, *radius, *start, *finish),
+        Op::Rect(w, h) => unsafe {
+            nvgRect(raw_ctx, 0f32, 0f32, *w, *h);
+        },
+        Op::RoundRect(w, h, r) => unsafe {
+            nvgRoundedRect(raw_ctx, 0f32, 0f32, *w, *h, *r);
+        },
+        Op::RoundRectVar => (),
+        Op::Ellipse(rx, ry) => unsafe {
+            nvgEllipse(raw_ctx, 0f32, 0f32, *rx, *ry);
+        },
+        Op::Circle(radius) => unsafe {
+            nvgCircle(raw_ctx, 0f32, 0f32, *radius);
+        },
+        Op::Sector {
+            radius,
+            start,
+            finish,
+        } => sector(raw_ctx, *radius, *start, *finish),
+        Op::Text(text) => draw_text(raw_ctx, text),
+
+        // transform operations
+        Op::TxReset => unsafe {
+            nvgResetTransform(raw_ctx);
+        },
+        Op::TxIdentity => (),
+        Op::TxMatrix([a, b, c, d, e, f]) => unsafe {
+            nvgTransform(raw_ctx, *a, *b, *c, *d, *e, *f);
+        },
+        Op::TxTranslate(x, y) => unsafe {
+            nvgTranslate(raw_ctx, *x, *y);
+        },
+        Op::TxScale(x, y) => unsafe {
+            nvgScale(raw_ctx, *x, *y);
+        },
+        Op::TxRotate(angle) => unsafe {
+            nvgRotate(raw_ctx, *angle);
+        },
+        Op::TxSkewX(angle) => unsafe {
+            nvgSkewX(raw_ctx, *angle);
+        },
+        Op::TxSkewY(angle) => unsafe {
+            nvgSkewY(raw_ctx, *angle);
+        },
+
+        //   // font styles
+        Op::Font(name) => font(raw_ctx, ctx.cache.font_id(&name.to_string_lossy())),
+        Op::FontId(handle) => font(raw_ctx, ctx.cache.font_id_by_handle(*handle)),
+        Op::FontBlur(blur) => unsafe {
+            nvgFontBlur(raw_ctx, *blur);
+        },
+        Op::FontSize(size) => unsafe {
+            nvgFontSize(raw_ctx, *size);
+        },
+        Op::TextAlign(align) => unsafe {
+            nvgTextAlign(raw_ctx, *align);
+        },
+        Op::TextHeight(height) => unsafe {
+            nvgTextLineHeight(raw_ctx, *height);
+        },
+        Op::Terminate => return Step::Done,
+    }
+    Step::Next(next_paint)
+}
+fn color(rgba: &Rgba) -> Color {
+    Color::from_rgba(rgba[0], rgba[1], rgba[2], rgba[3])
+}
+// a missing image was already reported by the cache lookup
+fn paint_image<'tx: 'e, 'e>(
+    image: Option<&'e Image<'tx>>,
+    origin: (f32, f32),
+    size: (f32, f32),
+    angle: f32,
+    alpha: u32,
 ) -> Option<Box<Paint + 'e>> {
-    let (ox, oy, mut ex, mut ey, angle, alpha, key_size) =
-        read_multi!(script, f32, f32, f32, f32, f32, u32, u32).unwrap();
-    let key = read_string(script, key_size as usize);
-
-    if let Some(image) = ctx.textures.get(&key) {
-        if ox == 0.0 && oy == 0.0 && ex == 0.0 && ey == 0.0 {
+    if let Some(image) = image {
+        let mut size = size;
+        if origin == (0.0, 0.0) && size == (0.0, 0.0) {
             let (w, h) = image.size();
-            ex = w as f32;
-            ey = h as f32;
+            size = (w as f32, h as f32);
         }
         Some(Box::new(ImagePattern {
             image: image,
-            origin: (ox, oy),
-            size: (ex, ey),
+            origin: origin,
+            size: size,
             angle: angle,
             alpha: alpha as f32 / 255.0,
         }))
     } else {
-        send_cache_miss(key);
         None
     }
 }
-fn shape_anti_alias(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgShapeAntiAlias(ctx, read_multi!(script, i32).unwrap());
-    }
-}
-fn shape_width(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgStrokeWidth(ctx, read_multi!(script, f32).unwrap());
-    }
-}
-fn stroke_color(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (r, g, b, a) = read_multi!(script, u32, u32, u32, u32).unwrap();
-    unsafe {
-        nvgStrokeColor(ctx, nvgRGBA(r as u8, g as u8, b as u8, a as u8));
-    }
-}
-fn fill_color(ctx: &NanoContext, script: &mut impl ReadBytesExt) {
-    read_multi!(script, Color).unwrap().fill(ctx)
-}
-fn miter_limit(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe { nvgMiterLimit(ctx, read_multi!(script, f32).unwrap()) }
-}
-fn line_cap(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe { nvgLineCap(ctx, read_multi!(script, i32).unwrap()) }
-}
-fn line_join(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
+fn line_join(ctx: *mut NVGcontext, join: i32) {
     unsafe {
         nvgLineCap(
             ctx,
-            match read_multi!(script, i32).unwrap() {
+            match join {
                 0 => NVGlineCap::NVG_MITER as i32,
                 1 => NVGlineCap::NVG_ROUND as i32,
                 2 => NVGlineCap::NVG_BEVEL as i32,
@@ -265,56 +356,11 @@ fn line_join(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
         )
     }
 }
-fn global_alpha(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe { nvgGlobalAlpha(ctx, read_multi!(script, f32).unwrap()) }
-}
-fn scissor(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (w, h) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgScissor(ctx, 0f32, 0f32, w, h);
-    }
-}
-fn intersect_scissor(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (w, h) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgIntersectScissor(ctx, 0f32, 0f32, w, h);
-    }
-}
-fn move_to(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x, y) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgMoveTo(ctx, x, y);
-    }
-}
-fn line_to(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x, y) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgLineTo(ctx, x, y);
-    }
-}
-fn bezier_to(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (c1x, c1y, c2x, c2y, x, y) = read_multi!(script, f32, f32, f32, f32, f32, f32).unwrap();
-    unsafe {
-        nvgBezierTo(ctx, c1x, c1y, c2x, c2y, x, y);
-    }
-}
-fn quadratic_to(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x1, y1, x2, y2) = read_multi!(script, f32, f32, f32, f32).unwrap();
-    unsafe {
-        nvgQuadTo(ctx, x1, y1, x2, y2);
-    }
-}
-fn arc_to(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x1, y1, x2, y2, radius) = read_multi!(script, f32, f32, f32, f32, f32).unwrap();
-    unsafe {
-        nvgArcTo(ctx, x1, y1, x2, y2, radius);
-    }
-}
-fn path_winding(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
+fn path_winding(ctx: *mut NVGcontext, solid: bool) {
     unsafe {
         nvgPathWinding(
             ctx,
-            if read_multi!(script, bool).unwrap() {
+            if solid {
                 NVGsolidity::NVG_SOLID
             } else {
                 NVGsolidity::NVG_HOLE
@@ -322,8 +368,8 @@ fn path_winding(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
         );
     }
 }
-fn triangle(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x0, y0, x1, y1, x2, y2) = read_multi!(script, f32, f32, f32, f32, f32, f32).unwrap();
+fn triangle(ctx: *mut NVGcontext, points: &[f32; 6]) {
+    let [x0, y0, x1, y1, x2, y2] = *points;
     unsafe {
         nvgMoveTo(ctx, x0, y0);
         nvgLineTo(ctx, x1, y1);
@@ -331,31 +377,7 @@ fn triangle(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
         nvgClosePath(ctx);
     }
 }
-fn rect(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (w, h) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgRect(ctx, 0f32, 0f32, w, h);
-    }
-}
-fn round_rect(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (w, h, r) = read_multi!(script, f32, f32, f32).unwrap();
-    unsafe {
-        nvgRoundedRect(ctx, 0f32, 0f32, w, h, r);
-    }
-}
-fn ellipse(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (rx, ry) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgEllipse(ctx, 0f32, 0f32, rx, ry);
-    }
-}
-fn circle(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgCircle(ctx, 0f32, 0f32, read_multi!(script, f32).unwrap());
-    }
-}
-fn arc(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (radius, start, finish) = read_multi!(script, f32, f32, f32).unwrap();
+fn arc(ctx: *mut NVGcontext, radius: f32, start: f32, finish: f32) {
     let mut angle = finish - start;
     angle = if angle > TAU { TAU } else { angle };
     angle = if angle < -TAU { -TAU } else { angle };
@@ -377,21 +399,18 @@ fn arc(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
         }
     }
 }
-fn sector(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    arc(ctx, script);
+fn sector(ctx: *mut NVGcontext, radius: f32, start: f32, finish: f32) {
+    arc(ctx, radius, start, finish);
     unsafe {
         nvgLineTo(ctx, 0f32, 0f32);
         nvgClosePath(ctx);
     }
 }
-fn text(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let len = read_multi!(script, usize).unwrap();
-    let text = read_bytes(script, len);
-
+fn draw_text(ctx: *mut NVGcontext, text: &[u8]) {
     unsafe {
         let mut height = 0f32;
-        let mut start: *const i8 = text.as_slice().as_ptr() as *const i8;
-        let end = start.offset(len as isize);
+        let mut start: *const i8 = text.as_ptr() as *const i8;
+        let end = start.offset(text.len() as isize);
         nvgTextMetrics(
             ctx,
             ::std::ptr::null_mut(),
@@ -417,69 +436,48 @@ fn text(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
     }
 }
 
-fn tx_rotate(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgRotate(ctx, read_multi!(script, f32).unwrap());
-    }
-}
-fn tx_translate(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x, y) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgTranslate(ctx, x, y);
-    }
-}
-fn tx_scale(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (x, y) = read_multi!(script, f32, f32).unwrap();
-    unsafe {
-        nvgScale(ctx, x, y);
-    }
-}
-fn tx_skew_x(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgSkewX(ctx, read_multi!(script, f32).unwrap());
-    }
-}
-fn tx_skew_y(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgSkewY(ctx, read_multi!(script, f32).unwrap());
-    }
-}
-fn tx_matrix(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let (a, b, c, d, e, f) = read_multi!(script, f32, f32, f32, f32, f32, f32).unwrap();
-    unsafe {
-        nvgTransform(ctx, a, b, c, d, e, f);
+// misses are reported by the cache lookup
+fn font(ctx: *mut NVGcontext, font_id: Option<i32>) {
+    if let Some(font_id) = font_id {
+        unsafe {
+            nvgFontFaceId(ctx, font_id);
+        }
     }
 }
 
-fn font(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    let len = read_multi!(script, usize).unwrap();
-    let text = read_string(script, len);
-    unsafe {
-        let font_id = nvgFindFont(ctx, text.as_bytes().as_ptr() as *const i8);
-        if font_id >= 0 {
-            nvgFontFaceId(ctx, font_id);
+// run_op needs a nanovg frame and so a GL context. these tests drive the op
+// loop with a stand-in for it, they cover the loop and not the ops themselves
+#[test]
+fn script_loop_long_test() {
+    let count = 200_000;
+    let mut ops = vec![Op::TxIdentity; count];
+    ops.push(Op::Terminate);
+    let script = decode_script(&encode_script(&ops)).expect("decode failed");
+    let mut seen = 0;
+    run_script_internal(&script, false, |_, op, _| {
+        seen += 1;
+        if *op == Op::Terminate {
+            Step::Done
         } else {
-            send_font_miss(text);
+            Step::Next(None)
         }
-    }
-}
-fn font_blur(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgFontBlur(ctx, read_multi!(script, f32).unwrap());
-    }
-}
-fn font_size(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgFontSize(ctx, read_multi!(script, f32).unwrap());
-    }
+    });
+    assert_eq!(seen, count + 1);
 }
-fn text_align(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgTextAlign(ctx, read_multi!(script, i32).unwrap());
-    }
-}
-fn text_height(ctx: *mut NVGcontext, script: &mut impl ReadBytesExt) {
-    unsafe {
-        nvgTextLineHeight(ctx, read_multi!(script, f32).unwrap());
-    }
+
+#[test]
+fn script_loop_paint_handoff_test() {
+    // PathBegin stands in for an op that makes a paint
+    let ops = vec![Op::PathBegin, Op::FillPaint, Op::Fill, Op::Terminate, Op::Fill];
+    let mut has_paint = vec![];
+    run_script_internal(&ops, false, |_, op, curr_paint| {
+        has_paint.push(curr_paint.is_some());
+        match op {
+            Op::PathBegin => Step::Next(Some(Box::new(Color::from_rgba(1, 2, 3, 4)))),
+            Op::FillPaint => Step::Next(curr_paint),
+            Op::Terminate => Step::Done,
+            _ => Step::Next(None),
+        }
+    });
+    assert_eq!(has_paint, vec![false, true, true, false]);
 }
@@ -0,0 +1,244 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::capture::parse_replay;
+use std::io::{self, stdin, stdout, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use crate::types::*;
+
+pub type Reader = Box<Read + Send>;
+pub type Writer = Box<Write + Send>;
+
+// where the port protocol comes from. every transport carries the same
+// 4-byte big-endian length framed messages
+pub trait Transport {
+    fn describe(&self) -> String;
+    // blocks until the Elixir side is connected
+    fn open(&self) -> io::Result<(Reader, Writer)>;
+    // whether a change of parent process means the Elixir side is gone
+    fn watch_parent(&self) -> bool {
+        false
+    }
+    // called once on the way out of the main loop
+    fn close(&self) {}
+}
+
+pub struct Stdio;
+
+impl Transport for Stdio {
+    fn describe(&self) -> String {
+        "stdio".to_string()
+    }
+    fn open(&self) -> io::Result<(Reader, Writer)> {
+        Ok((Box::new(stdin()), Box::new(stdout())))
+    }
+    // the port spawned us, if the BEAM dies we get reparented
+    fn watch_parent(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Transport for UnixSocket {
+    fn describe(&self) -> String {
+        format!("unix:{}", self.path.display())
+    }
+    fn open(&self) -> io::Result<(Reader, Writer)> {
+        remove_stale_socket(&self.path)?;
+        let listener = UnixListener::bind(&self.path)?;
+        let (stream, _) = listener.accept()?;
+        let writer = SocketWriter {
+            stream: stream.try_clone()?,
+            _listener: listener,
+        };
+        Ok((Box::new(stream), Box::new(writer)))
+    }
+    fn close(&self) {
+        let _ = ::std::fs::remove_file(&self.path);
+    }
+}
+
+// the writer side of a unix socket connection
+#[cfg(unix)]
+struct SocketWriter {
+    stream: UnixStream,
+    // kept open so a second driver started on the same path finds it in use
+    _listener: UnixListener,
+}
+
+#[cfg(unix)]
+impl Write for SocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// a socket file left behind by a previous run would make bind fail. one that
+// a running driver still listens on, or anything else at that path, is left
+// alone
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match ::std::fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            if !metadata.file_type().is_socket() {
+                Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            } else {
+                match UnixStream::connect(path) {
+                    Ok(_) => Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use", path.display()),
+                    )),
+                    Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                        ::std::fs::remove_file(path)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+pub struct Tcp {
+    pub port: u16,
+}
+
+impl Transport for Tcp {
+    fn describe(&self) -> String {
+        format!("tcp:{}", self.port)
+    }
+    fn open(&self) -> io::Result<(Reader, Writer)> {
+        // only ever listen on localhost, the protocol has no authentication
+        let listener = TcpListener::bind(("127.0.0.1", self.port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+    }
+}
+
+// "stdio", "unix:/path/to/socket", "tcp:port" or "replay:/path/to/capture[@speed]"
+pub fn parse_transport(spec: &str) -> Result<Box<Transport>, String> {
+    if spec == "stdio" {
+        return Ok(Box::new(Stdio));
+    }
+    if spec.starts_with("unix:") {
+        return parse_unix(&spec["unix:".len()..]);
+    }
+    if spec.starts_with("replay:") {
+        return parse_replay(&spec["replay:".len()..]).map(|replay| Box::new(replay) as Box<Transport>);
+    }
+    if spec.starts_with("tcp:") {
+        let port = &spec["tcp:".len()..];
+        return port
+            .parse::<u16>()
+            .map(|port| Box::new(Tcp { port: port }) as Box<Transport>)
+            .map_err(|_| format!("invalid tcp port: {}", port));
+    }
+    Err(format!("unknown transport: {}", spec))
+}
+
+#[cfg(unix)]
+fn parse_unix(path: &str) -> Result<Box<Transport>, String> {
+    if path.is_empty() {
+        return Err("unix transport needs a socket path".to_string());
+    }
+    Ok(Box::new(UnixSocket {
+        path: PathBuf::from(path),
+    }))
+}
+
+#[cfg(not(unix))]
+fn parse_unix(_path: &str) -> Result<Box<Transport>, String> {
+    Err("unix sockets are not supported on this platform".to_string())
+}
+
+pub fn read_msg(read: &mut impl Read) -> io::Result<Message> {
+    let len = read.read_u32::<BigEndian>()? as usize;
+    let mut buf = vec![0u8; len];
+    read.read_exact(buf.as_mut_slice())?;
+    Ok(buf)
+}
+
+// leaves flushing to the caller
+pub fn write_frame(write: &mut impl Write, msg: &[u8]) -> io::Result<()> {
+    write.write_u32::<BigEndian>(msg.len() as u32)?;
+    write.write_all(msg)
+}
+
+#[test]
+fn parse_transport_test() {
+    assert_eq!(parse_transport("stdio").unwrap().describe(), "stdio");
+    assert_eq!(parse_transport("tcp:4000").unwrap().describe(), "tcp:4000");
+    assert!(parse_transport("tcp:").is_err());
+    assert!(parse_transport("tcp:70000").is_err());
+    assert!(parse_transport("pipe").is_err());
+    assert_eq!(
+        parse_transport("replay:/tmp/session.cap@2").unwrap().describe(),
+        "replay:/tmp/session.cap@2"
+    );
+    #[cfg(unix)]
+    {
+        assert_eq!(
+            parse_transport("unix:/tmp/scenic.sock").unwrap().describe(),
+            "unix:/tmp/scenic.sock"
+        );
+        assert!(parse_transport("unix:").is_err());
+    }
+}
+
+#[test]
+fn framing_over_tcp_test() {
+    use std::net::TcpStream;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = ::std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write_frame(&mut stream, &[1, 2, 3]).unwrap();
+        write_frame(&mut stream, &[]).unwrap();
+        read_msg(&mut stream).unwrap()
+    });
+    let (mut stream, _) = listener.accept().unwrap();
+    assert_eq!(read_msg(&mut stream).unwrap(), vec![1, 2, 3]);
+    assert_eq!(read_msg(&mut stream).unwrap(), Vec::<u8>::new());
+    write_frame(&mut stream, &[4, 5]).unwrap();
+    assert_eq!(client.join().unwrap(), vec![4, 5]);
+}
+
+#[cfg(unix)]
+#[test]
+fn remove_stale_socket_test() {
+    let dir = ::std::env::temp_dir();
+    let file = dir.join(format!("scenic_not_a_socket_{}", ::std::process::id()));
+    ::std::fs::write(&file, b"notes").unwrap();
+    assert!(remove_stale_socket(&file).is_err());
+    assert!(file.exists());
+    ::std::fs::remove_file(&file).unwrap();
+
+    let socket = dir.join(format!("scenic_stale_socket_{}", ::std::process::id()));
+    let listener = UnixListener::bind(&socket).unwrap();
+    assert_eq!(
+        remove_stale_socket(&socket).unwrap_err().kind(),
+        io::ErrorKind::AddrInUse
+    );
+    assert!(socket.exists());
+    drop(listener);
+    remove_stale_socket(&socket).unwrap();
+    assert!(!socket.exists());
+    // nothing there at all is fine too
+    remove_stale_socket(&socket).unwrap();
+}
@@ -1,32 +1,146 @@
+use crate::cache::{rgba_pixels, ResourceCache};
+use crate::cursor::CursorCache;
+use crate::display::{update_content_scale, WindowAttribs};
 use crate::event::*;
-use glfw::Window;
-use nanovg::Image;
-use std::collections::HashMap;
+use glfw::{Cursor, CursorMode, Glfw, Window, WindowEvent};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use crate::defines::CMD_UPDATE_GRAPH;
+use crate::error::ProtocolError;
+use crate::liveness::Liveness;
+use crate::ops::{splice_script, Op, OpSplice};
 use crate::comms::*;
-pub type Script = Vec<u8>;
+pub type Script = Rc<Vec<Op>>;
 pub type NanoContext = ::nanovg::Context;
 pub struct Context<'ctx: 'tx, 'tx> {
-    pub textures: HashMap<String, Image<'tx>>,
+    pub cache: ResourceCache<'tx>,
     pub ctx: &'ctx NanoContext,
 }
 
 impl<'ctx: 'tx, 'tx> Context<'ctx, 'tx> {
     pub fn put_tx(&mut self, key: String, data: Vec<u8>) {
-        if let Ok(img) = ::nanovg::Image::new(&self.ctx).build_from_memory(&data) {
-            self.textures.insert(key, img);
-        } else {
-            send_puts(format!("build image failed: {}", key));
+        match ::nanovg::Image::new(&self.ctx).build_from_memory(&data) {
+            Ok(img) => {
+                self.cache.textures.put(&key, img);
+            }
+            Err(_) => send_puts(format!("build image failed: {}", key)),
         }
     }
+    pub fn put_tx_raw(
+        &mut self,
+        key: String,
+        size: (u32, u32),
+        format: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let pixels = rgba_pixels(size.0, size.1, format, data)?;
+        let img = ::nanovg::Image::new(self.ctx)
+            .build_from_rgba(size.0 as usize, size.1 as usize, &pixels)
+            .map_err(|_| format!("build image failed: {}", key))?;
+        self.cache.textures.put(&key, img);
+        Ok(())
+    }
     pub fn free_tx(&mut self, key: String) {
-        self.textures.remove(&key);
+        self.cache.textures.remove(&key);
+    }
+    pub fn put_font(&mut self, key: String, data: Vec<u8>) -> Result<(), String> {
+        self.cache.put_font(self.ctx, &key, data)
+    }
+    pub fn free_font(&mut self, key: String) {
+        self.cache.remove_font(&key);
+    }
+    pub fn load_resource(&mut self, kind: u32, key: String, data: Vec<u8>) -> Result<u32, String> {
+        self.cache.load(self.ctx, kind, &key, data)
+    }
+    pub fn release_resource(&mut self, kind: u32, key: String) -> Result<(), String> {
+        self.cache.release(kind, &key)
     }
 }
 
 pub type Message = Vec<u8>;
 
+pub const DEFAULT_MAX_SCRIPT_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptNestingError {
+    Cycle(Vec<u32>),
+    TooDeep(Vec<u32>),
+}
+
+impl ScriptNestingError {
+    pub fn chain(&self) -> &[u32] {
+        match self {
+            ScriptNestingError::Cycle(chain) | ScriptNestingError::TooDeep(chain) => chain,
+        }
+    }
+}
+
+impl fmt::Display for ScriptNestingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let chain: Vec<String> = self.chain().iter().map(|id| id.to_string()).collect();
+        match self {
+            ScriptNestingError::Cycle(_) => write!(f, "script cycle: {}", chain.join(" -> ")),
+            ScriptNestingError::TooDeep(_) => {
+                write!(f, "script nesting too deep: {}", chain.join(" -> "))
+            }
+        }
+    }
+}
+
+// the chain of scripts currently being run, used to stop OP_RUN_SCRIPT
+// from recursing forever
+pub struct ScriptStack {
+    ids: Vec<u32>,
+    pub max_depth: usize,
+    reported: HashSet<Vec<u32>>,
+}
+
+impl ScriptStack {
+    pub fn new(max_depth: usize) -> ScriptStack {
+        ScriptStack {
+            ids: vec![],
+            max_depth: max_depth,
+            reported: Default::default(),
+        }
+    }
+    pub fn check(&self, id: u32) -> Result<(), ScriptNestingError> {
+        let cycle = self.ids.contains(&id);
+        if !cycle && self.ids.len() < self.max_depth {
+            return Ok(());
+        }
+        // only a failing check pays for the chain
+        let mut chain = self.ids.clone();
+        chain.push(id);
+        if cycle {
+            Err(ScriptNestingError::Cycle(chain))
+        } else {
+            Err(ScriptNestingError::TooDeep(chain))
+        }
+    }
+    pub fn push(&mut self, id: u32) {
+        self.ids.push(id);
+    }
+    pub fn pop(&mut self) {
+        self.ids.pop();
+    }
+    pub fn current(&self) -> Option<u32> {
+        self.ids.last().cloned()
+    }
+    // true the first time a chain is seen, so a broken graph is reported
+    // once instead of every frame
+    pub fn first_report(&mut self, err: &ScriptNestingError) -> bool {
+        self.reported.insert(err.chain().to_vec())
+    }
+    pub fn clear_reports(&mut self) {
+        self.reported.clear();
+    }
+}
+
 pub struct WindowData {
     pub window: Window,
+    pub events: Receiver<(f64, WindowEvent)>,
     pub window_size: (i32, i32),
     pub frame_size: (i32, i32),
     pub keep_going: bool,
@@ -34,24 +148,62 @@ pub struct WindowData {
     pub input_flags: u32,
     pub last_cursor_pos: (f32, f32),
     pub scripts: HashMap<u32, Script>,
+    pub script_stack: ScriptStack,
     pub root_script: i32,
+    pub liveness: Liveness,
+    // x, y, width and height to go back to when leaving fullscreen
+    pub windowed_rect: (i32, i32, i32, i32),
+    pub cursors: CursorCache<Cursor>,
+    pub cursor_mode: CursorMode,
+    // last reported to MSG_OUT_MAXIMIZE
+    pub maximized: bool,
+    // the monitor's scale, 2.0 on a HiDPI screen whether or not the window
+    // size is already counted in pixels
+    pub content_scale: (f32, f32),
+    // set by a move or resize, which may have changed the content scale
+    pub rescale_pending: bool,
+    // set by any size or scale change, sent as one MSG_OUT_RESHAPE
+    pub reshape_pending: bool,
+    pub attribs: WindowAttribs,
+    // GL state is per context, so each window keeps its own
+    pub clear_color: [f32; 4],
 }
 
 impl WindowData {
     pub fn put_script(&mut self, id: u32, script: Script) {
         self.scripts.insert(id, script);
+        self.script_stack.clear_reports();
+    }
+    // a script that is being drawn right now keeps the old ops, the patched
+    // copy is picked up on the next frame
+    pub fn patch_script(&mut self, id: u32, splices: Vec<OpSplice>) -> Result<(), ProtocolError> {
+        let script = self.scripts.get_mut(&id).ok_or_else(|| {
+            ProtocolError::new(CMD_UPDATE_GRAPH, 0, "no such script").in_script(id)
+        })?;
+        splice_script(Rc::make_mut(script), splices).map_err(|err| err.in_script(id))?;
+        self.script_stack.clear_reports();
+        Ok(())
     }
     pub fn get_script(&self, id: u32) -> Option<Script> {
-        self.scripts.get(&id).map(|x| x.clone())
+        self.scripts.get(&id).cloned()
     }
     pub fn delete_script(&mut self, id: u32) {
         self.scripts.remove(&id);
+        self.script_stack.clear_reports();
     }
     pub fn get_ratio(&self) -> (f32, f32) {
-        (
-            self.frame_size.0 as f32 / self.window_size.0 as f32,
-            self.frame_size.1 as f32 / self.window_size.1 as f32,
-        )
+        pixel_ratio(self.window_size, self.frame_size)
+    }
+    // nanovg takes one ratio, the finer axis keeps text sharp on both
+    pub fn get_pixel_ratio(&self) -> f32 {
+        let (x, y) = self.get_ratio();
+        x.max(y)
+    }
+    // input positions are in window coordinates, the same units as the
+    // window size in MSG_OUT_RESHAPE and the size scripts are drawn at
+    pub fn get_cursor_pos(&self) -> (f32, f32) {
+        let (x, y) = self.window.get_cursor_pos();
+        (x as f32, y as f32)
     }
     pub fn get_window_size_float(&self) -> (f32, f32) {
         let (width, height) = self.window_size;
@@ -59,15 +211,49 @@ impl WindowData {
     }
 }
 
-pub fn setup_window_data(window: Window, width: i32, height: i32) -> WindowData {
+// framebuffer pixels per window coordinate on each axis. a minimized window
+// can report a size of 0, which counts as 1:1
+pub fn pixel_ratio(window_size: (i32, i32), frame_size: (i32, i32)) -> (f32, f32) {
+    let ratio = |window: i32, frame: i32| {
+        if window > 0 && frame > 0 {
+            frame as f32 / window as f32
+        } else {
+            1.0
+        }
+    };
+    (
+        ratio(window_size.0, frame_size.0),
+        ratio(window_size.1, frame_size.1),
+    )
+}
+
+pub fn setup_window_data(
+    glfw: &mut Glfw,
+    window: Window,
+    events: Receiver<(f64, WindowEvent)>,
+    width: i32,
+    height: i32,
+) -> WindowData {
     let mut re = WindowData {
         window: window,
+        events: events,
         keep_going: true,
         redraw: false,
         input_flags: 0xFFFF,
         last_cursor_pos: (-1f32, -1f32),
         scripts: Default::default(),
+        script_stack: ScriptStack::new(DEFAULT_MAX_SCRIPT_DEPTH),
         root_script: -1,
+        liveness: Liveness::new(),
+        windowed_rect: (0, 0, width, height),
+        cursors: CursorCache::new(),
+        cursor_mode: CursorMode::Normal,
+        maximized: false,
+        content_scale: (1.0, 1.0),
+        rescale_pending: false,
+        reshape_pending: false,
+        attribs: WindowAttribs::default(),
+        clear_color: [0.0, 0.0, 0.0, 1.0],
         window_size: (width, height),
         frame_size: (0, 0),
     };
@@ -77,6 +263,10 @@ pub fn setup_window_data(window: Window, width: i32, height: i32) -> WindowData
 
     let size = re.window.get_size();
     reshape_window(&mut re, size.0, size.1);
+    re.maximized = re.window.is_maximized();
+
+    update_content_scale(glfw, &mut re);
+    send_pending_reshape(&mut re);
 
     re.window.set_framebuffer_size_polling(true);
     re.window.set_size_polling(true);
@@ -87,8 +277,39 @@ pub fn setup_window_data(window: Window, width: i32, height: i32) -> WindowData
     re.window.set_mouse_button_polling(true);
     re.window.set_scroll_polling(true);
     re.window.set_close_polling(true);
-    unsafe {
-        ::gl::ClearColor(0f32, 0f32, 0f32, 1f32);
-    }
+    re.window.set_drag_and_drop_polling(true);
+    re.window.set_focus_polling(true);
+    re.window.set_iconify_polling(true);
+    re.window.set_pos_polling(true);
     re
 }
+
+#[test]
+fn pixel_ratio_test() {
+    assert_eq!(pixel_ratio((800, 600), (800, 600)), (1.0, 1.0));
+    assert_eq!(pixel_ratio((800, 600), (1600, 1200)), (2.0, 2.0));
+    assert_eq!(pixel_ratio((1280, 720), (1920, 1080)), (1.5, 1.5));
+    assert_eq!(pixel_ratio((800, 600), (1600, 900)), (2.0, 1.5));
+    assert_eq!(pixel_ratio((0, 0), (0, 0)), (1.0, 1.0));
+    assert_eq!(pixel_ratio((800, 0), (1600, 0)), (2.0, 1.0));
+}
+
+#[test]
+fn script_stack_test() {
+    let mut stack = ScriptStack::new(3);
+    assert_eq!(stack.check(1), Ok(()));
+    stack.push(1);
+    stack.push(2);
+    assert_eq!(stack.check(1), Err(ScriptNestingError::Cycle(vec![1, 2, 1])));
+    assert_eq!(stack.check(3), Ok(()));
+    stack.push(3);
+    let err = stack.check(4).unwrap_err();
+    assert_eq!(err, ScriptNestingError::TooDeep(vec![1, 2, 3, 4]));
+    assert!(stack.first_report(&err));
+    assert!(!stack.first_report(&err));
+    stack.clear_reports();
+    assert!(stack.first_report(&err));
+    stack.pop();
+    assert_eq!(stack.current(), Some(2));
+    assert_eq!(stack.check(4), Ok(()));
+}
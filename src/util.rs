@@ -24,20 +24,25 @@ pub fn check_gl_error(msg: String) {
     }
 }
 fn trim_null_tail(mut buf: Vec<u8>) -> Vec<u8> {
-    let mut i = buf.len() - 1;
-    while buf[i] == 0 {
-        i = i - 1;
+    while buf.last() == Some(&0) {
+        buf.pop();
     }
-    buf.truncate(i + 1);
     buf
 }
-pub fn read_string(read: &mut impl Read, len: usize) -> String {
-    String::from_utf8(trim_null_tail(read_bytes(read, len))).unwrap()
-}
-pub fn read_bytes(read: &mut impl Read, len: usize) -> Vec<u8> {
-    let mut re: Vec<u8> = vec![0u8; len];
-    read.read_exact(re.as_mut_slice()).unwrap();
-    re
+pub fn read_string(read: &mut impl Read, len: usize) -> ::std::io::Result<String> {
+    String::from_utf8(trim_null_tail(read_bytes(read, len)?))
+        .map_err(|err| ::std::io::Error::new(::std::io::ErrorKind::InvalidData, err))
+}
+pub fn read_bytes(read: &mut impl Read, len: usize) -> ::std::io::Result<Vec<u8>> {
+    let mut re: Vec<u8> = vec![];
+    read.take(len as u64).read_to_end(&mut re)?;
+    if re.len() < len {
+        return Err(::std::io::Error::new(
+            ::std::io::ErrorKind::UnexpectedEof,
+            format!("expected {} bytes, got {}", len, re.len()),
+        ));
+    }
+    Ok(re)
 }
 
 trait ReadTuple: Sized {
@@ -137,6 +142,22 @@ fn read_write_multi_test() {
     assert_eq!(vec, vec![1, 2, 3, 4, 5, 6, 7, 0xff]);
 }
 
+#[test]
+fn read_string_test() {
+    use std::io::Cursor;
+    let mut r = Cursor::new(b"abc\0\0\0\0".to_vec());
+    assert_eq!(read_string(&mut r, 7).unwrap(), "abc");
+
+    let mut r = Cursor::new(vec![0u8; 4]);
+    assert_eq!(read_string(&mut r, 4).unwrap(), "");
+
+    let mut r = Cursor::new(vec![0xffu8, 0xfe]);
+    assert!(read_string(&mut r, 2).is_err());
+
+    let mut r = Cursor::new(b"ab".to_vec());
+    assert!(read_string(&mut r, 4).is_err());
+}
+
 pub trait ScenicResult<T> {
     fn expect_or_send(self, msg: &str) -> T;
 }
@@ -145,6 +166,7 @@ impl<T, E: ::std::fmt::Debug> ScenicResult<T> for Result<T, E> {
     fn expect_or_send(self, msg: &str) -> T {
         self.map_err(|err| {
             send_puts(msg.to_string());
+            close_output();
             err
         }).expect(msg)
     }
@@ -0,0 +1,106 @@
+use crate::comms::*;
+use crate::display::{set_window_attribs, WindowAttribs};
+use glfw::{Context, Glfw, WindowHint, WindowMode};
+use std::collections::BTreeMap;
+use crate::types::*;
+
+// the window opened from the command line, commands that are not wrapped in
+// CMD_WINDOW go to it and its messages go out untagged
+pub const MAIN_WINDOW: u32 = 0;
+
+// every open window by the id Elixir gave it. they all share the GL objects
+// of the main window, so one nanovg context and one resource cache serve all
+pub struct Windows {
+    windows: BTreeMap<u32, WindowData>,
+    // the launch options new windows are created with
+    hints: Vec<WindowHint>,
+    attribs: WindowAttribs,
+}
+
+impl Windows {
+    pub fn new(main: WindowData, hints: Vec<WindowHint>, attribs: WindowAttribs) -> Windows {
+        let mut windows = BTreeMap::new();
+        windows.insert(MAIN_WINDOW, main);
+        Windows {
+            windows: windows,
+            hints: hints,
+            attribs: attribs,
+        }
+    }
+
+    pub fn main(&mut self) -> &mut WindowData {
+        self.windows
+            .get_mut(&MAIN_WINDOW)
+            .expect("the main window is never closed")
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut WindowData> {
+        self.windows.get_mut(&id)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u32, &mut WindowData)> {
+        self.windows
+            .iter_mut()
+            .map(|(id, window_data)| (*id, window_data))
+    }
+
+    // the driver stops when the main window gets CMD_QUIT
+    pub fn keep_going(&self) -> bool {
+        self.windows[&MAIN_WINDOW].keep_going
+    }
+
+    // for commands that change what every window draws, like a texture load
+    pub fn redraw_all(&mut self) {
+        for window_data in self.windows.values_mut() {
+            window_data.redraw = true;
+        }
+    }
+
+    pub fn open(
+        &mut self,
+        glfw: &mut Glfw,
+        id: u32,
+        size: (i32, i32),
+        resizable: bool,
+        title: &str,
+    ) -> Result<(), String> {
+        if self.windows.contains_key(&id) {
+            return Err(format!("window {} is already open", id));
+        }
+        if size.0 <= 0 || size.1 <= 0 {
+            return Err(format!("invalid window size: {}x{}", size.0, size.1));
+        }
+        // back to glfw's defaults first, so hints only the main window was
+        // made with, like starting hidden or unfocused, do not carry over
+        glfw.default_window_hints();
+        for hint in &self.hints {
+            glfw.window_hint(*hint);
+        }
+        glfw.window_hint(WindowHint::Resizable(resizable));
+        let (window, events) = self
+            .main()
+            .window
+            .create_shared(size.0 as u32, size.1 as u32, title, WindowMode::Windowed)
+            .ok_or_else(|| format!("cannot create window {}", id))?;
+        // the new window's reshape goes out tagged with its id
+        let mut window_data = with_window(id, || {
+            setup_window_data(glfw, window, events, size.0, size.1)
+        });
+        window_data.script_stack.max_depth = self.main().script_stack.max_depth;
+        set_window_attribs(&mut window_data, self.attribs.clone());
+        self.windows.insert(id, window_data);
+        Ok(())
+    }
+
+    pub fn close(&mut self, id: u32) -> Result<(), String> {
+        if id == MAIN_WINDOW {
+            return Err("the main window closes with CMD_QUIT".to_string());
+        }
+        self.windows
+            .remove(&id)
+            .ok_or_else(|| format!("no window {}", id))?;
+        // its GL context may have been the current one
+        self.main().window.make_current();
+        Ok(())
+    }
+}